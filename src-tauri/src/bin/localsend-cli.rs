@@ -0,0 +1,630 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, Subcommand};
+use console::{style, user_attended};
+use dialoguer::Password;
+use indicatif::{MultiProgress, ProgressBar};
+use localsend_rs_lib::{
+    core::{
+        sender::SendClient,
+        service::{app_state, spawn_discovery, spawn_server},
+        trust::{
+            admit, certificate_fingerprint, check, forget, load_trusted_devices,
+            save_trusted_devices, update_pin, Trust,
+        },
+        utils::{init_tracing, ALIAS, MULTICAST_PORT},
+    },
+    models::{
+        DeviceInfo, Identity, ReceivePolicy, ReceiveState, ReceiveStatus, SendError, SenderMessage,
+        ServerMessage, Settings,
+    },
+    terminal::{progress_bar, Terminal},
+};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+// exit codes, 2 is used by clap for invalid arguments
+const EXIT_FAILED: u8 = 1; // the transfer failed or some of the files could not be transferred
+const EXIT_DECLINED: u8 = 3;
+const EXIT_BUSY: u8 = 4;
+const EXIT_NOT_FOUND: u8 = 5;
+const EXIT_UNAUTHORIZED: u8 = 6; // the receiver wants a PIN that wasn't given or was wrong
+const EXIT_UNTRUSTED: u8 = 7; // the certificate of the device doesn't match its fingerprint
+const EXIT_CANCELLED: u8 = 130;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Send and receive files with LocalSend devices without a display"
+)]
+struct Cli {
+    /// Name other devices see this device as
+    #[arg(long, global = true, default_value = ALIAS)]
+    alias: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the devices on the network
+    Scan {
+        /// Seconds to listen for devices
+        #[arg(long, default_value_t = 3)]
+        timeout: u64,
+        /// Print the devices as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Receive files from other devices
+    Receive {
+        /// Accept every request without asking, requests over the auto accept limits of the
+        /// settings are declined
+        #[arg(long, conflicts_with_all = ["prompt", "favorites"])]
+        auto_accept: bool,
+        /// Accept the requests of favorite devices without asking and ask about the others
+        #[arg(long, conflicts_with = "prompt")]
+        favorites: bool,
+        /// Ask which files to receive, by default the receive policy of the settings is used
+        #[arg(long)]
+        prompt: bool,
+        /// Directory to save received files in instead of the download directory
+        #[arg(long)]
+        dest: Option<PathBuf>,
+        /// Exit after the first session ends or the first message is received
+        #[arg(long)]
+        once: bool,
+        /// Only accept requests of senders that know this PIN, instead of the PIN of the settings
+        #[arg(long)]
+        pin: Option<String>,
+    },
+    /// Send files and folders, or a text message, to a device
+    Send {
+        #[arg(required_unless_present = "text")]
+        paths: Vec<PathBuf>,
+        /// Send this text as a message instead of files
+        #[arg(long, conflicts_with = "paths")]
+        text: Option<String>,
+        /// Alias, ip address or fingerprint of the receiver
+        #[arg(long)]
+        to: String,
+        /// Port of the receiver if it is given by its ip address
+        #[arg(long, default_value_t = MULTICAST_PORT)]
+        port: u16,
+        /// Seconds to look for the receiver on the network
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+        /// PIN of the receiver, it is asked for if the receiver requires one and it isn't given
+        #[arg(long)]
+        pin: Option<String>,
+    },
+    /// Print the fingerprint other devices know this device by
+    Identity {
+        /// Replace the certificate and fingerprint with new ones first
+        #[arg(long)]
+        regenerate: bool,
+    },
+    /// List, verify and forget the devices whose fingerprints were pinned when they were first seen
+    Trust {
+        #[command(subcommand)]
+        command: TrustCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrustCommand {
+    /// List the pinned devices
+    List {
+        /// Print the devices as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check that the certificate a device serves matches its pin, it is pinned if it isn't yet
+    Verify {
+        /// Alias, ip address or fingerprint of the device
+        device: String,
+        /// Port of the device if it is given by its ip address
+        #[arg(long, default_value_t = MULTICAST_PORT)]
+        port: u16,
+        /// Seconds to look for the device on the network
+        #[arg(long, default_value_t = 5)]
+        timeout: u64,
+    },
+    /// Forget the pin of a device, it is pinned again the next time it is seen
+    Forget {
+        /// Alias, ip address or fingerprint of the device
+        device: String,
+    },
+}
+
+/// What `send` sends
+enum Content {
+    Files(Vec<PathBuf>),
+    Text(String),
+}
+
+/// A device as printed by `scan --json`
+#[derive(Serialize)]
+struct ScanEntry<'a> {
+    ip: &'a str,
+    #[serde(flatten)]
+    device: &'a DeviceInfo,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let Cli { alias, command } = Cli::parse();
+    init_tracing();
+    match command {
+        Command::Scan { timeout, json } => scan(&alias, Duration::from_secs(timeout), json).await,
+        Command::Receive {
+            auto_accept,
+            favorites,
+            prompt,
+            dest,
+            once,
+            pin,
+        } => {
+            let receive_policy = match (auto_accept, favorites, prompt) {
+                (true, _, _) => Some(ReceivePolicy::Everyone),
+                (_, true, _) => Some(ReceivePolicy::Favorites),
+                (_, _, true) => Some(ReceivePolicy::Ask),
+                _ => None,
+            };
+            receive(&alias, receive_policy, auto_accept, dest, once, pin).await
+        }
+        Command::Send {
+            paths,
+            text,
+            to,
+            port,
+            timeout,
+            pin,
+        } => {
+            let content = match text {
+                Some(text) => Content::Text(text),
+                None => Content::Files(paths),
+            };
+            let timeout = Duration::from_secs(timeout);
+            send(&alias, content, &to, port, timeout, pin).await
+        }
+        Command::Identity { regenerate } => identity(regenerate),
+        Command::Trust { command } => match command {
+            TrustCommand::List { json } => list_trusted_devices(json),
+            TrustCommand::Verify {
+                device,
+                port,
+                timeout,
+            } => verify(&alias, &device, port, Duration::from_secs(timeout)).await,
+            TrustCommand::Forget { device } => forget_trusted_device(&device),
+        },
+    }
+}
+
+fn identity(regenerate: bool) -> ExitCode {
+    let identity = if regenerate {
+        match Identity::regenerate() {
+            Ok(identity) => identity,
+            Err(err) => {
+                eprintln!("Could not regenerate the identity: {}", err);
+                return ExitCode::from(EXIT_FAILED);
+            }
+        }
+    } else {
+        Identity::load()
+    };
+    println!("{}", identity.fingerprint);
+    ExitCode::SUCCESS
+}
+
+fn list_trusted_devices(json: bool) -> ExitCode {
+    let trusted_devices = load_trusted_devices();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&trusted_devices).unwrap()
+        );
+        return ExitCode::SUCCESS;
+    }
+    for trusted_device in &trusted_devices {
+        println!(
+            "{} {} {} since {}",
+            style(&trusted_device.alias).bold(),
+            trusted_device.ip,
+            style(&trusted_device.fingerprint).dim(),
+            trusted_device.first_seen
+        );
+    }
+    if trusted_devices.is_empty() {
+        eprintln!("No devices are pinned");
+    }
+    ExitCode::SUCCESS
+}
+
+async fn verify(alias: &str, device: &str, port: u16, timeout: Duration) -> ExitCode {
+    let (app_state, _) = app_state(alias, Settings::load());
+    let Some(target) = resolve_device(&app_state, device, port, timeout).await else {
+        eprintln!("Could not find a device called {}", style(device).bold());
+        return ExitCode::from(EXIT_NOT_FOUND);
+    };
+    let fingerprint = match certificate_fingerprint(&target).await {
+        Ok(Some(fingerprint)) => fingerprint,
+        Ok(None) => {
+            eprintln!(
+                "{} doesn't use https, there is no certificate to verify",
+                style(&target.alias).bold()
+            );
+            return ExitCode::from(EXIT_FAILED);
+        }
+        Err(err) => {
+            eprintln!("{}", style(&err).red());
+            return ExitCode::from(EXIT_FAILED);
+        }
+    };
+    if !target.fingerprint.is_empty() && target.fingerprint != fingerprint {
+        eprintln!(
+            "{}",
+            style(format!(
+                "The certificate of {} doesn't match the fingerprint it announced",
+                target.alias
+            ))
+            .red()
+        );
+        return ExitCode::from(EXIT_UNTRUSTED);
+    }
+
+    let mut state = app_state.lock().await;
+    let mut device_info = DeviceInfo {
+        fingerprint,
+        ..target
+    };
+    // a device given by its ip is checked against the pin of the device last seen there
+    if let Some(trusted_device) = state.trusted_devices.iter().find(|trusted_device| {
+        device_info.alias == device_info.ip && trusted_device.ip == device_info.ip
+    }) {
+        device_info.alias = trusted_device.alias.clone();
+    }
+    match check(&state.trusted_devices, &device_info) {
+        Trust::Known(index) => {
+            update_pin(&mut state, &device_info);
+            println!(
+                "{} matches the fingerprint pinned on {}",
+                style(&device_info.alias).bold(),
+                state.trusted_devices[index].first_seen
+            );
+            ExitCode::SUCCESS
+        }
+        Trust::New | Trust::NewAtPinnedIp(_) => {
+            // a new device at the ip of another pin is reported by `admit`
+            admit(&mut state, &device_info);
+            println!(
+                "{} wasn't pinned yet, it is now pinned to {}",
+                style(&device_info.alias).bold(),
+                device_info.fingerprint
+            );
+            ExitCode::SUCCESS
+        }
+        Trust::Mismatch(pinned) => {
+            eprintln!(
+                "{}",
+                style(format!(
+                    "{} serves the certificate {} but {} ({}) was pinned to {}",
+                    device_info.alias,
+                    device_info.fingerprint,
+                    pinned.alias,
+                    pinned.ip,
+                    pinned.fingerprint
+                ))
+                .red()
+            );
+            ExitCode::from(EXIT_UNTRUSTED)
+        }
+    }
+}
+
+fn forget_trusted_device(device: &str) -> ExitCode {
+    let mut trusted_devices = load_trusted_devices();
+    let forgotten = forget(&mut trusted_devices, device);
+    if forgotten.is_empty() {
+        eprintln!("No pinned device called {}", style(device).bold());
+        return ExitCode::from(EXIT_NOT_FOUND);
+    }
+    if let Err(err) = save_trusted_devices(&trusted_devices) {
+        eprintln!("Could not save the pinned devices: {}", err);
+        return ExitCode::from(EXIT_FAILED);
+    }
+    for trusted_device in forgotten {
+        println!(
+            "Forgot {} {}",
+            style(&trusted_device.alias).bold(),
+            style(&trusted_device.fingerprint).dim()
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+async fn scan(alias: &str, timeout: Duration, json: bool) -> ExitCode {
+    let (app_state, _) = app_state(alias, Settings::load());
+    spawn_discovery(&app_state);
+    tokio::time::sleep(timeout).await;
+    let devices = app_state.lock().await.device.devices.clone();
+
+    if json {
+        let entries = devices
+            .iter()
+            .map(|device| ScanEntry {
+                ip: &device.ip,
+                device,
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+        return ExitCode::SUCCESS;
+    }
+    for device in &devices {
+        println!(
+            "{} {}:{} {} ({}) {}",
+            style(&device.alias).bold(),
+            device.ip,
+            device.port,
+            device.device_model.as_deref().unwrap_or("unknown"),
+            device.device_type,
+            style(&device.fingerprint).dim()
+        );
+    }
+    if devices.is_empty() {
+        eprintln!("No devices found");
+    }
+    ExitCode::SUCCESS
+}
+
+async fn receive(
+    alias: &str,
+    receive_policy: Option<ReceivePolicy>,
+    auto_accept: bool,
+    dest: Option<PathBuf>,
+    once: bool,
+    pin: Option<String>,
+) -> ExitCode {
+    let mut settings = Settings::load();
+    if let Some(dest) = dest {
+        settings.download_directory = dest;
+    }
+    if let Some(receive_policy) = receive_policy {
+        settings.receive_policy = receive_policy;
+    }
+    if pin.is_some() {
+        settings.receive_pin = pin;
+    }
+    let (app_state, mut server_rx) = app_state(alias, settings);
+    spawn_discovery(&app_state);
+    spawn_server(&app_state);
+
+    let mut terminal = Terminal::new(auto_accept);
+    loop {
+        let server_message = tokio::select! {
+            server_message = server_rx.recv() => match server_message {
+                Some(server_message) => server_message,
+                None => return ExitCode::from(EXIT_FAILED),
+            },
+            _ = tokio::signal::ctrl_c() => return ExitCode::from(EXIT_CANCELLED),
+        };
+        let exit_code = match &server_message {
+            ServerMessage::SessionFinished((_, ReceiveStatus::Finished)) => Some(ExitCode::SUCCESS),
+            ServerMessage::SessionFinished(_) | ServerMessage::SessionTimedOut(_) => {
+                Some(ExitCode::from(EXIT_FAILED))
+            }
+            ServerMessage::CancelSession(_) => Some(ExitCode::from(EXIT_CANCELLED)),
+            ServerMessage::TextMessage(_) => Some(ExitCode::SUCCESS),
+            _ => None,
+        };
+        terminal.handle(server_message);
+        if let Some(exit_code) = exit_code.filter(|_| once) {
+            return exit_code;
+        }
+    }
+}
+
+async fn send(
+    alias: &str,
+    content: Content,
+    to: &str,
+    port: u16,
+    timeout: Duration,
+    mut pin: Option<String>,
+) -> ExitCode {
+    let (app_state, _) = app_state(alias, Settings::load());
+    let Some(mut target) = resolve_device(&app_state, to, port, timeout).await else {
+        eprintln!("Could not find a device called {}", style(to).bold());
+        return ExitCode::from(EXIT_NOT_FOUND);
+    };
+    let info = {
+        let state = app_state.lock().await;
+        // a receiver given by its ip has to serve the certificate pinned for the ip
+        if target.fingerprint.is_empty() {
+            if let Some(trusted_device) = state
+                .trusted_devices
+                .iter()
+                .find(|trusted_device| trusted_device.ip == target.ip)
+            {
+                target.fingerprint = trusted_device.fingerprint.clone();
+                target.alias = trusted_device.alias.clone();
+            }
+        }
+        state.device.register_request()
+    };
+
+    let is_text = matches!(content, Content::Text(_));
+    let cancel_token = CancellationToken::new();
+    let ctrl_c_token = cancel_token.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            ctrl_c_token.cancel();
+        }
+    });
+
+    let result = loop {
+        let (progress_tx, progress_rx) = mpsc::unbounded_channel();
+        let show_progress = tokio::spawn(show_send_progress(
+            target.alias.clone(),
+            is_text,
+            progress_rx,
+        ));
+        let send_client = SendClient::new(info.clone()).with_pin(pin.clone());
+        let result = match &content {
+            Content::Files(paths) => {
+                send_client
+                    .send_files(&target, paths, progress_tx, cancel_token.clone())
+                    .await
+            }
+            Content::Text(text) => {
+                send_client
+                    .send_text(&target, text.clone(), progress_tx, cancel_token.clone())
+                    .await
+            }
+        };
+        let _ = show_progress.await;
+
+        match result {
+            // ask for the PIN until the receiver gives up on this device
+            Err(err @ (SendError::PinRequired | SendError::WrongPin)) if user_attended() => {
+                eprintln!("{}", style(&err).red());
+                pin = Some(Password::new().with_prompt("PIN").interact().unwrap());
+            }
+            result => break result,
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            // the certificate of the receiver matched its fingerprint
+            update_pin(&mut *app_state.lock().await, &target);
+            if is_text {
+                println!(
+                    "Sent the message to {}",
+                    style(&target.alias).bold().magenta()
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", style(&err).red());
+            match err {
+                SendError::FilesFailed(failed) => {
+                    for (file_name, err) in failed {
+                        eprintln!("  {}: {}", file_name, err);
+                    }
+                    ExitCode::from(EXIT_FAILED)
+                }
+                SendError::Declined => ExitCode::from(EXIT_DECLINED),
+                SendError::Busy => ExitCode::from(EXIT_BUSY),
+                SendError::PinRequired | SendError::WrongPin | SendError::TooManyAttempts => {
+                    ExitCode::from(EXIT_UNAUTHORIZED)
+                }
+                SendError::Cancelled => ExitCode::from(EXIT_CANCELLED),
+                SendError::FingerprintMismatch => ExitCode::from(EXIT_UNTRUSTED),
+                _ => ExitCode::from(EXIT_FAILED),
+            }
+        }
+    }
+}
+
+/// The device at the ip address `to`, or the nearby device whose alias or fingerprint is `to`
+async fn resolve_device(
+    app_state: &ReceiveState,
+    to: &str,
+    port: u16,
+    timeout: Duration,
+) -> Option<DeviceInfo> {
+    match to.parse::<IpAddr>() {
+        Ok(ip) => Some(DeviceInfo {
+            alias: to.to_string(),
+            ip: ip.to_string(),
+            port,
+            ..Default::default()
+        }),
+        Err(_) => {
+            spawn_discovery(app_state);
+            find_device(app_state, to, timeout).await
+        }
+    }
+}
+
+/// Waits for a device whose alias or fingerprint is `to` to show up
+async fn find_device(app_state: &ReceiveState, to: &str, timeout: Duration) -> Option<DeviceInfo> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let device = app_state
+            .lock()
+            .await
+            .device
+            .devices
+            .iter()
+            .find(|device| device.alias == to || device.fingerprint == to)
+            .cloned();
+        if device.is_some() || Instant::now() >= deadline {
+            return device;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+async fn show_send_progress(
+    alias: String,
+    is_text: bool, // a message that was shown by the receiver isn't uploaded
+    mut progress_rx: mpsc::UnboundedReceiver<SenderMessage>,
+) {
+    let multi_progress = MultiProgress::new();
+    let mut files = HashMap::new();
+    let mut progress_map: HashMap<String, ProgressBar> = HashMap::new();
+
+    while let Some(sender_message) = progress_rx.recv().await {
+        match sender_message {
+            SenderMessage::Waiting => {
+                println!(
+                    "Waiting for {} to accept...",
+                    style(&alias).bold().magenta()
+                )
+            }
+            SenderMessage::Accepted(accepted) => {
+                if accepted.is_empty() && !is_text {
+                    println!("{} did not accept any file", style(&alias).bold().magenta());
+                }
+                for file_info in accepted {
+                    progress_map.insert(
+                        file_info.id.clone(),
+                        progress_bar(&multi_progress, &file_info),
+                    );
+                    files.insert(file_info.id.clone(), file_info.file_name);
+                }
+            }
+            SenderMessage::Progress((file_id, sent)) => {
+                if let Some(pb) = progress_map.get(&file_id) {
+                    pb.set_position(sent);
+                }
+            }
+            SenderMessage::FileFinished(file_id) => {
+                if let Some(pb) = progress_map.remove(&file_id) {
+                    pb.finish_and_clear();
+                    let _ = multi_progress.println(format!("Sent {}", files[&file_id]));
+                }
+            }
+            SenderMessage::FileFailed((file_id, err)) => {
+                if let Some(pb) = progress_map.remove(&file_id) {
+                    pb.finish_and_clear();
+                    let _ = multi_progress
+                        .println(format!("{} could not be sent: {}", files[&file_id], err));
+                }
+            }
+        }
+    }
+    for pb in progress_map.values() {
+        pb.finish_and_clear();
+    }
+}
@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+/// Returns the first of `name.ext`, `name (1).ext`, `name (2).ext`, ... that is not taken.
+pub fn unique_path(path: &Path, is_taken: impl Fn(&Path) -> bool) -> PathBuf {
+    if !is_taken(path) {
+        return path.to_path_buf();
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    (1..)
+        .map(|idx| path.with_file_name(format!("{} ({}){}", stem, idx, extension)))
+        .find(|candidate| !is_taken(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unique(path: &str, taken: &[&str]) -> PathBuf {
+        unique_path(Path::new(path), |candidate| {
+            taken.iter().any(|taken| candidate == Path::new(taken))
+        })
+    }
+
+    #[test]
+    fn keeps_free_names() {
+        assert_eq!(unique("dir/photo.jpg", &[]), Path::new("dir/photo.jpg"));
+    }
+
+    #[test]
+    fn numbers_taken_names() {
+        assert_eq!(
+            unique("dir/photo.jpg", &["dir/photo.jpg"]),
+            Path::new("dir/photo (1).jpg")
+        );
+        assert_eq!(
+            unique(
+                "dir/photo.jpg",
+                &["dir/photo.jpg", "dir/photo (1).jpg", "dir/photo (2).jpg"]
+            ),
+            Path::new("dir/photo (3).jpg")
+        );
+    }
+
+    #[test]
+    fn numbers_names_without_extension() {
+        assert_eq!(
+            unique("dir/README", &["dir/README"]),
+            Path::new("dir/README (1)")
+        );
+    }
+
+    #[test]
+    fn numbers_dotfiles_after_the_name() {
+        assert_eq!(
+            unique("dir/.bashrc", &["dir/.bashrc"]),
+            Path::new("dir/.bashrc (1)")
+        );
+    }
+
+    #[test]
+    fn numbers_before_the_last_extension() {
+        assert_eq!(
+            unique("dir/backup.tar.gz", &["dir/backup.tar.gz"]),
+            Path::new("dir/backup.tar (1).gz")
+        );
+    }
+}
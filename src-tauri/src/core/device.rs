@@ -4,21 +4,24 @@ use std::{
     time::Duration,
 };
 
-use crate::models::{DeviceInfo, DeviceResponse, LocalSendDevice};
+use crate::models::{DeviceInfo, DeviceResponse, LocalSendDevice, RegisterRequest};
 use tokio::net::UdpSocket;
-use tokio::sync::mpsc::Sender;
-use uuid::Uuid;
+use tokio::sync::{mpsc::Sender, watch};
+use tracing::trace;
 
-use super::utils::{get_device_ip_addr, BUFFER_SIZE, DEVICE_MODEL, DEVICE_TYPE, NUM_REPEAT};
+use super::utils::{
+    get_device_ip_addr, BUFFER_SIZE, DEVICE_MODEL, DEVICE_TYPE, NUM_REPEAT, PROTOCOL,
+    PROTOCOL_VERSION,
+};
 
 impl LocalSendDevice {
     pub fn new(
         device_alias: String,
+        fingerprint: String, // of the certificate the server uses
         interface_addr: Ipv4Addr,
         multicast_addr: Ipv4Addr,
         multicast_port: u16,
     ) -> Self {
-        let fingerprint = Uuid::new_v4();
         let ip_addr = get_device_ip_addr().unwrap_or(IpAddr::V4([0, 0, 0, 0].into()));
 
         let device_info = DeviceInfo {
@@ -27,12 +30,14 @@ impl LocalSendDevice {
             device_model: Some(DEVICE_MODEL.to_string()),
             ip: ip_addr.to_string(),
             port: multicast_port,
+            version: Some(PROTOCOL_VERSION.to_string()),
+            protocol: Some(PROTOCOL.to_string()),
+            fingerprint,
             ip_ending: Some(ip_addr.to_string().split(".").last().unwrap().to_string()),
         };
         let this_device = DeviceResponse {
             device_info,
             announcement: true,
-            fingerprint: fingerprint.to_string(),
         };
 
         Self {
@@ -45,6 +50,21 @@ impl LocalSendDevice {
         }
     }
 
+    /// This device as it introduces itself in `/register` and outgoing send requests
+    pub fn register_request(&self) -> RegisterRequest {
+        let device_info = &self.this_device.device_info;
+        RegisterRequest {
+            alias: device_info.alias.clone(),
+            version: PROTOCOL_VERSION.to_string(),
+            device_model: device_info.device_model.clone(),
+            device_type: device_info.device_type.clone(),
+            fingerprint: device_info.fingerprint.clone(),
+            port: device_info.port,
+            protocol: PROTOCOL.to_string(),
+            download: Some(false),
+        }
+    }
+
     pub async fn connect(&mut self) {
         let socket = Arc::new(
             UdpSocket::bind((self.interface_addr, self.multicast_port))
@@ -66,13 +86,22 @@ impl LocalSendDevice {
             .unwrap();
     }
 
+    /// `this_device` serialized for the multicast group, `announcement` asks the others to
+    /// answer
+    fn announcement_msg(this_device: &DeviceResponse, announcement: bool) -> String {
+        let mut this_device = this_device.clone();
+        this_device.announcement = announcement;
+        serde_json::to_string(&this_device).unwrap()
+    }
+
     pub async fn announce_repeat(
         send_socket: Arc<UdpSocket>,
-        announcement_msg: String,
+        this_device: watch::Receiver<DeviceResponse>,
         addr: (Ipv4Addr, u16),
     ) {
         // TODO(notjedi): any other way to not accept addr as argument
         loop {
+            let announcement_msg = Self::announcement_msg(&this_device.borrow(), true);
             for _ in 0..NUM_REPEAT {
                 Self::announce(&send_socket, announcement_msg.as_str(), addr).await;
             }
@@ -80,9 +109,12 @@ impl LocalSendDevice {
         }
     }
 
+    /// Announces `this_device`, which changes when the identity of the device is regenerated,
+    /// and reports the devices that announce themselves to `sender`
     pub async fn listen_and_announce_multicast(
         &mut self,
         socket: Arc<UdpSocket>,
+        this_device: watch::Receiver<DeviceResponse>,
         sender: Sender<Vec<DeviceInfo>>,
     ) {
         // https://gist.github.com/pusateri/df98511b88e9000f388d344a1f3db9e7
@@ -90,28 +122,25 @@ impl LocalSendDevice {
             .join_multicast_v4(self.multicast_addr, self.interface_addr)
             .expect("failed to join multicast");
 
-        self.this_device.announcement = true;
         let send_socket = socket.clone();
-        let announce_msg = serde_json::to_string(&self.this_device).unwrap();
         tokio::spawn(Self::announce_repeat(
             send_socket,
-            announce_msg,
+            this_device.clone(),
             (self.multicast_addr, self.multicast_port),
         ));
 
-        self.this_device.announcement = false;
-        let reply_announce_msg = serde_json::to_string(&self.this_device).unwrap();
-
         let mut buf = [0u8; BUFFER_SIZE as usize];
         loop {
             if let Ok((amt, src)) = socket.recv_from(&mut buf).await {
                 let mut device_response: DeviceResponse =
                     serde_json::from_slice(&buf[..amt]).unwrap();
-                (
-                    device_response.device_info.ip,
-                    device_response.device_info.port,
-                ) = (src.ip().to_string(), src.port());
+                device_response.device_info.ip = src.ip().to_string();
+                // v1 devices don't announce the port of their server, it is the multicast port
+                if device_response.device_info.port == 0 {
+                    device_response.device_info.port = src.port();
+                }
 
+                self.this_device = this_device.borrow().clone();
                 if device_response == self.this_device {
                     continue;
                 }
@@ -119,17 +148,17 @@ impl LocalSendDevice {
                 if device_response.announcement {
                     Self::announce(
                         &socket,
-                        reply_announce_msg.as_str(),
+                        Self::announcement_msg(&self.this_device, false).as_str(),
                         (self.multicast_addr, self.multicast_port),
                     )
                     .await;
                 }
 
                 if !self.devices.contains(&device_response.device_info) {
-                    device_response.device_info.ip_ending = Some(src.ip().to_string().split(".").last().unwrap().to_string());
+                    device_response.device_info.ip_ending =
+                        Some(src.ip().to_string().split(".").last().unwrap().to_string());
                     self.devices.push(device_response.device_info);
-                    println!("{:#?}", &self.devices);
-                    println!("{:#?}", &self.devices.len());
+                    trace!("{:#?}", &self.devices);
                     let _ = sender.send(self.devices.clone()).await;
                 }
             }
@@ -0,0 +1,49 @@
+//! Devices and files that the tests of several modules build their cases from
+
+use crate::models::{DeviceInfo, FileInfo, FileType, TrustedDevice};
+
+pub const PHONE_FINGERPRINT: &str = "aabbcc";
+pub const PHONE_IP: &str = "192.168.1.20";
+
+pub fn device_info(alias: &str, ip: &str, fingerprint: &str) -> DeviceInfo {
+    DeviceInfo {
+        alias: alias.into(),
+        device_type: "mobile".into(),
+        device_model: None,
+        ip: ip.into(),
+        port: 53317,
+        version: Some("2.0".into()),
+        protocol: Some("https".into()),
+        fingerprint: fingerprint.into(),
+        ip_ending: None,
+    }
+}
+
+pub fn trusted_device(alias: &str, ip: &str, fingerprint: &str) -> TrustedDevice {
+    TrustedDevice {
+        fingerprint: fingerprint.into(),
+        alias: alias.into(),
+        ip: ip.into(),
+        first_seen: "2024-01-01T00:00:00+00:00".into(),
+    }
+}
+
+/// A phone and a laptop, each pinned at its own ip
+pub fn pinned() -> Vec<TrustedDevice> {
+    vec![
+        trusted_device("Phone", PHONE_IP, PHONE_FINGERPRINT),
+        trusted_device("Laptop", "192.168.1.21", "ddeeff"),
+    ]
+}
+
+/// A file whose id is its name
+pub fn file_info(file_name: &str, file_type: FileType, size: usize) -> FileInfo {
+    FileInfo {
+        id: file_name.into(),
+        size,
+        file_name: file_name.into(),
+        file_type,
+        sha256: None,
+        preview: None,
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::Local;
+
+use crate::models::{HistoryEntry, HistoryFile, ReceiveSession};
+
+use super::utils::{APP_DIR_NAME, MAX_HISTORY_ENTRIES};
+
+// sessions that end at the same time would overwrite each other's entry
+static HISTORY_LOCK: Mutex<()> = Mutex::new(());
+
+impl HistoryEntry {
+    pub fn from_session(receive_session: &ReceiveSession) -> Self {
+        let mut files = receive_session
+            .files
+            .iter()
+            .map(|(file_id, file_info)| HistoryFile {
+                file_name: file_info.file_name.clone(),
+                path: receive_session.file_paths[file_id].clone(),
+                size: file_info.size,
+                status: receive_session.file_status[file_id].clone(),
+                error: receive_session.file_errors.get(file_id).cloned(),
+                preview: file_info.preview.clone(),
+                preview_path: None,
+            })
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+        Self {
+            session_id: receive_session.session_id.clone(),
+            sender_alias: receive_session.sender.alias.clone(),
+            received_at: Local::now().to_rfc3339(),
+            status: receive_session.status.clone(),
+            folder: receive_session.root_folder.clone(),
+            files,
+        }
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|data_dir| data_dir.join(APP_DIR_NAME).join("history.json"))
+}
+
+fn previews_directory() -> Option<PathBuf> {
+    dirs::data_dir().map(|data_dir| data_dir.join(APP_DIR_NAME).join("previews"))
+}
+
+/// Reads the persisted history, newest session first
+pub fn load_history() -> Vec<HistoryEntry> {
+    path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|history| serde_json::from_str(&history).ok())
+        .unwrap_or_default()
+}
+
+/// Reads the persisted history along with the previews of its files
+pub fn load_history_with_previews() -> Vec<HistoryEntry> {
+    let mut history = load_history();
+    for file in history.iter_mut().flat_map(|entry| entry.files.iter_mut()) {
+        if let Some(preview_path) = &file.preview_path {
+            file.preview = fs::read_to_string(preview_path).ok();
+        }
+    }
+    history
+}
+
+/// Adds `entry` to the front of the history, dropping the oldest sessions past
+/// `MAX_HISTORY_ENTRIES`. Blocks on file io, previews are written to files of their own.
+pub fn record_history(mut entry: HistoryEntry) -> io::Result<()> {
+    let no_data_directory =
+        || io::Error::new(io::ErrorKind::NotFound, "No data directory on this system");
+    let path = path().ok_or_else(no_data_directory)?;
+    let previews_directory = previews_directory().ok_or_else(no_data_directory)?;
+    let _history_lock = HISTORY_LOCK.lock().unwrap();
+
+    for (index, file) in entry.files.iter_mut().enumerate() {
+        if let Some(preview) = file.preview.take() {
+            fs::create_dir_all(&previews_directory)?;
+            let preview_path = previews_directory.join(format!("{}-{}", entry.session_id, index));
+            fs::write(&preview_path, preview)?;
+            file.preview_path = Some(preview_path);
+        }
+    }
+    let mut history = load_history();
+    history.insert(0, entry);
+    let dropped = history.split_off(history.len().min(MAX_HISTORY_ENTRIES));
+    for file in dropped.iter().flat_map(|entry| &entry.files) {
+        if let Some(preview_path) = &file.preview_path {
+            let _ = fs::remove_file(preview_path);
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&history)?)
+}
+
+/// Folder that was sent in a session, given the relative path of every accepted file and the
+/// directory it is saved in. Files sent from more than one folder share `destination_directory`.
+pub fn root_folder<'a>(
+    files: impl IntoIterator<Item = (&'a Path, &'a Path)>,
+    destination_directory: &Path,
+) -> Option<PathBuf> {
+    let mut roots = files
+        .into_iter()
+        .filter(|(relative_path, _)| relative_path.components().count() > 1)
+        .filter_map(|(relative_path, directory)| {
+            relative_path
+                .components()
+                .next()
+                .map(|top| directory.join(top))
+        })
+        .collect::<Vec<_>>();
+    roots.sort();
+    roots.dedup();
+    match roots.as_slice() {
+        [] => None,
+        [root] => Some(root.clone()),
+        _ => Some(destination_directory.to_path_buf()),
+    }
+}
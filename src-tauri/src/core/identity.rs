@@ -0,0 +1,123 @@
+use std::{fs, io, path::PathBuf};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    sign::CertifiedKey,
+};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::models::Identity;
+
+use super::utils::{generate_cert, to_hex, APP_DIR_NAME};
+
+impl Identity {
+    /// Reads the identity of this device, it is created the first time
+    pub fn load() -> Self {
+        if let Some(identity) = Self::read() {
+            return identity;
+        }
+        Self::regenerate().unwrap_or_else(|err| {
+            warn!(
+                "could not save the identity, it changes with every launch: {}",
+                err
+            );
+            Self::generate()
+        })
+    }
+
+    /// Replaces the identity of this device with a new one, other devices won't recognize it
+    /// anymore
+    pub fn regenerate() -> io::Result<Self> {
+        let (certificate_path, private_key_path) = paths().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "No data directory on this system")
+        })?;
+        let identity = Self::generate();
+        if let Some(parent) = certificate_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_private(&private_key_path, &identity.private_key_pem)?;
+        fs::write(certificate_path, &identity.certificate_pem)?;
+        Ok(identity)
+    }
+
+    fn read() -> Option<Self> {
+        let (certificate_path, private_key_path) = paths()?;
+        let certificate_pem = fs::read_to_string(certificate_path).ok()?;
+        let private_key_pem = fs::read_to_string(private_key_path).ok()?;
+        if !keys_match(&certificate_pem, &private_key_pem) {
+            warn!("the private key doesn't belong to the certificate, creating a new identity");
+            return None;
+        }
+        let fingerprint = fingerprint(&certificate_pem)?;
+        Some(Self {
+            certificate_pem,
+            private_key_pem,
+            fingerprint,
+        })
+    }
+
+    fn generate() -> Self {
+        let (certificate, key_pair) = generate_cert();
+        Self {
+            certificate_pem: certificate.pem(),
+            private_key_pem: key_pair.serialize_pem(),
+            fingerprint: to_hex(&Sha256::digest(certificate.der())),
+        }
+    }
+}
+
+fn paths() -> Option<(PathBuf, PathBuf)> {
+    let directory = dirs::data_dir()?.join(APP_DIR_NAME);
+    Some((
+        directory.join("certificate.pem"),
+        directory.join("private_key.pem"),
+    ))
+}
+
+/// The sha256 of the der encoded certificate, which is what the protocol uses as fingerprint
+fn fingerprint(certificate_pem: &str) -> Option<String> {
+    let certificate = CertificateDer::from_pem_slice(certificate_pem.as_bytes()).ok()?;
+    Some(to_hex(&Sha256::digest(certificate)))
+}
+
+/// Whether `private_key_pem` is the key of `certificate_pem`, the server can't use them otherwise
+fn keys_match(certificate_pem: &str, private_key_pem: &str) -> bool {
+    let Ok(certificate) = CertificateDer::from_pem_slice(certificate_pem.as_bytes()) else {
+        return false;
+    };
+    let Ok(private_key) = PrivateKeyDer::from_pem_slice(private_key_pem.as_bytes()) else {
+        return false;
+    };
+    let provider = rustls::crypto::aws_lc_rs::default_provider();
+    let Ok(key) = provider.key_provider.load_private_key(private_key) else {
+        return false;
+    };
+    CertifiedKey::new(vec![certificate], key)
+        .keys_match()
+        .is_ok()
+}
+
+/// Writes the private key so that only the current user can read it
+#[cfg(unix)]
+fn write_private(path: &PathBuf, contents: &str) -> io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+
+    // the mode only applies to new files, the key is written to one and moved over the old file,
+    // which may be readable by others
+    let temp_path = path.with_extension("pem.tmp");
+    let _ = fs::remove_file(&temp_path);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &PathBuf, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
@@ -1,3 +1,16 @@
+pub mod conflict;
 pub mod device;
+#[cfg(test)]
+pub mod fixtures;
+pub mod history;
+pub mod identity;
+pub mod policy;
+pub mod preview;
+pub mod progress;
+pub mod sanitize;
+pub mod sender;
 pub mod server;
+pub mod service;
+pub mod settings;
+pub mod trust;
 pub mod utils;
@@ -0,0 +1,304 @@
+use std::{collections::HashMap, fmt, path::Path};
+
+use indicatif::HumanBytes;
+
+use crate::models::{FileInfo, ReceivePolicy, SendRequest, Settings, SpaceShortage, TrustedDevice};
+
+use super::{
+    trust::{check, Trust},
+    utils::available_space,
+};
+
+/// Whether a send request is accepted without asking the user, along with the rule that decided
+pub enum Decision {
+    Accept(String),
+    Ask(String),
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Accept(rule) => write!(f, "accepted without asking, {}", rule),
+            Self::Ask(rule) => write!(f, "asking the user, {}", rule),
+        }
+    }
+}
+
+/// Applies the receive policy and the auto accept limits of `settings` to `send_request`.
+/// Requests with a `space_shortage` are always asked about.
+pub fn decide(
+    settings: &Settings,
+    trusted_devices: &[TrustedDevice],
+    send_request: &SendRequest,
+    space_shortage: Option<&SpaceShortage>,
+) -> Decision {
+    let device_info = &send_request.device_info;
+    let rule = match settings.receive_policy {
+        ReceivePolicy::Ask => return Decision::Ask("the receive policy is to ask".into()),
+        ReceivePolicy::Everyone => "the receive policy is to accept everyone".to_string(),
+        ReceivePolicy::Favorites => {
+            // v1 senders don't send a fingerprint, they can't be told apart
+            let favorite = settings.favorite_devices.iter().find(|favorite| {
+                !device_info.fingerprint.is_empty()
+                    && favorite.fingerprint == device_info.fingerprint
+            });
+            // the fingerprint is broadcast with every announcement, anyone could claim it. The
+            // request has to come from the ip the device was pinned with.
+            let pinned_ip = match check(trusted_devices, device_info) {
+                Trust::Known(index) => trusted_devices[index].ip == device_info.ip,
+                _ => false,
+            };
+            match favorite {
+                Some(favorite) if !pinned_ip => {
+                    return Decision::Ask(format!(
+                        "{} is a favorite device but not at the ip it was pinned with",
+                        favorite.alias
+                    ))
+                }
+                Some(favorite) => format!("{} is a favorite device", favorite.alias),
+                None => {
+                    return Decision::Ask(format!(
+                        "{} ({}) is not a favorite device",
+                        device_info.alias, device_info.fingerprint
+                    ))
+                }
+            }
+        }
+    };
+
+    match exceeded_limit(settings, send_request, space_shortage) {
+        Some(limit) => Decision::Ask(limit),
+        None => Decision::Accept(rule),
+    }
+}
+
+/// The first auto accept limit that `send_request` goes over, if any
+fn exceeded_limit(
+    settings: &Settings,
+    send_request: &SendRequest,
+    space_shortage: Option<&SpaceShortage>,
+) -> Option<String> {
+    let total_size = send_request
+        .files
+        .values()
+        .map(|file_info| file_info.size as u64)
+        .sum::<u64>();
+    if let Some(max_size) = settings.auto_accept_max_size {
+        if total_size > max_size {
+            return Some(format!(
+                "{} is more than the limit of {}",
+                HumanBytes(total_size),
+                HumanBytes(max_size)
+            ));
+        }
+    }
+    if let Some(space_shortage) = space_shortage {
+        return Some(format!(
+            "{} don't fit in {}",
+            HumanBytes(space_shortage.needed),
+            space_shortage.directory
+        ));
+    }
+
+    for file_info in send_request.files.values() {
+        if !settings.auto_accept_file_types.is_empty()
+            && !settings
+                .auto_accept_file_types
+                .contains(&file_info.file_type)
+        {
+            return Some(format!(
+                "{} is a {:?} file, which is not allowed",
+                file_info.file_name, file_info.file_type
+            ));
+        }
+        let extension = Path::new(&file_info.file_name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if !settings.auto_accept_extensions.is_empty()
+            && !settings.auto_accept_extensions.contains(&extension)
+        {
+            return Some(format!(
+                "the extension of {} is not allowed",
+                file_info.file_name
+            ));
+        }
+    }
+    None
+}
+
+/// The first destination directory that has less space left than the files of `alias` that are
+/// saved there, with `route_by_file_type` files can end up in different directories
+pub fn space_shortage<'a>(
+    settings: &Settings,
+    alias: &str,
+    files: impl IntoIterator<Item = &'a FileInfo>,
+) -> Option<SpaceShortage> {
+    let mut needed = HashMap::new();
+    for file_info in files {
+        *needed
+            .entry(settings.destination_directory(alias, &file_info.file_type))
+            .or_insert(0) += file_info.size as u64;
+    }
+    needed.into_iter().find_map(|(directory, needed)| {
+        available_space(&directory)
+            .filter(|free| *free < needed)
+            .map(|free| SpaceShortage {
+                directory: directory.to_string_lossy().into_owned(),
+                needed,
+                free,
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        core::fixtures::{
+            device_info, file_info, pinned, PHONE_FINGERPRINT as FINGERPRINT, PHONE_IP as IP,
+        },
+        models::{DeviceInfo, FavoriteDevice, FileType},
+    };
+
+    use super::*;
+
+    fn send_request(device_info: DeviceInfo, files: Vec<FileInfo>) -> SendRequest {
+        SendRequest {
+            device_info,
+            files: files
+                .into_iter()
+                .map(|file_info| (file_info.id.clone(), file_info))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    fn photo_from(fingerprint: &str, ip: &str) -> SendRequest {
+        send_request(
+            device_info("Phone", ip, fingerprint),
+            vec![file_info("photo.jpg", FileType::Image, 1000)],
+        )
+    }
+
+    fn settings(receive_policy: ReceivePolicy) -> Settings {
+        Settings {
+            receive_policy,
+            favorite_devices: vec![FavoriteDevice {
+                fingerprint: FINGERPRINT.into(),
+                alias: "Phone".into(),
+            }],
+            ..Settings::default()
+        }
+    }
+
+    fn accepted(settings: &Settings, send_request: &SendRequest) -> bool {
+        matches!(
+            decide(settings, &pinned(), send_request, None),
+            Decision::Accept(_)
+        )
+    }
+
+    #[test]
+    fn ask_asks_about_everything() {
+        let settings = settings(ReceivePolicy::Ask);
+        assert!(!accepted(&settings, &photo_from(FINGERPRINT, IP)));
+    }
+
+    #[test]
+    fn everyone_accepts_unknown_devices() {
+        let settings = settings(ReceivePolicy::Everyone);
+        assert!(accepted(&settings, &photo_from("ddeeff", "192.168.1.30")));
+        assert!(accepted(&settings, &photo_from("", "192.168.1.30")));
+    }
+
+    #[test]
+    fn favorites_accepts_favorites_at_their_pinned_ip() {
+        let settings = settings(ReceivePolicy::Favorites);
+        assert!(accepted(&settings, &photo_from(FINGERPRINT, IP)));
+        assert!(!accepted(&settings, &photo_from("ddeeff", IP)));
+    }
+
+    #[test]
+    fn favorites_asks_about_a_claimed_fingerprint_from_another_ip() {
+        let settings = settings(ReceivePolicy::Favorites);
+        assert!(!accepted(
+            &settings,
+            &photo_from(FINGERPRINT, "192.168.1.30")
+        ));
+    }
+
+    #[test]
+    fn favorites_asks_about_devices_without_a_fingerprint() {
+        let mut settings = settings(ReceivePolicy::Favorites);
+        // a favorite that was saved without a fingerprint doesn't match v1 senders either
+        settings.favorite_devices.push(FavoriteDevice {
+            fingerprint: String::new(),
+            alias: "Old phone".into(),
+        });
+        assert!(!accepted(&settings, &photo_from("", IP)));
+    }
+
+    #[test]
+    fn asks_about_requests_over_the_size_limit() {
+        let mut settings = settings(ReceivePolicy::Everyone);
+        settings.auto_accept_max_size = Some(1000);
+        assert!(accepted(&settings, &photo_from(FINGERPRINT, IP)));
+        settings.auto_accept_max_size = Some(999);
+        assert!(!accepted(&settings, &photo_from(FINGERPRINT, IP)));
+    }
+
+    #[test]
+    fn asks_about_requests_that_dont_fit() {
+        let settings = settings(ReceivePolicy::Everyone);
+        let space_shortage = SpaceShortage {
+            directory: "/downloads".into(),
+            needed: 1000,
+            free: 10,
+        };
+        let decision = decide(
+            &settings,
+            &pinned(),
+            &photo_from(FINGERPRINT, IP),
+            Some(&space_shortage),
+        );
+        assert!(matches!(decision, Decision::Ask(_)));
+    }
+
+    #[test]
+    fn finds_directories_without_enough_space() {
+        let settings = Settings {
+            download_directory: std::env::temp_dir(),
+            ..Settings::default()
+        };
+        let small = file_info("small.txt", FileType::Text, 1);
+        let huge = file_info("huge.bin", FileType::Other, usize::MAX / 2);
+        assert!(space_shortage(&settings, "Phone", [&small]).is_none());
+        assert!(space_shortage(&settings, "Phone", [&small, &huge]).is_some());
+    }
+
+    #[test]
+    fn asks_about_file_types_that_are_not_allowed() {
+        let mut settings = settings(ReceivePolicy::Everyone);
+        settings.auto_accept_file_types = vec![FileType::Image];
+        assert!(accepted(&settings, &photo_from(FINGERPRINT, IP)));
+        settings.auto_accept_file_types = vec![FileType::Video, FileType::Pdf];
+        assert!(!accepted(&settings, &photo_from(FINGERPRINT, IP)));
+    }
+
+    #[test]
+    fn asks_about_extensions_that_are_not_allowed() {
+        let mut settings = settings(ReceivePolicy::Everyone);
+        settings.auto_accept_extensions = vec!["jpg".into()];
+        let upper_case = send_request(
+            device_info("Phone", IP, FINGERPRINT),
+            vec![file_info("PHOTO.JPG", FileType::Image, 1000)],
+        );
+        assert!(accepted(&settings, &upper_case));
+        let without_extension = send_request(
+            device_info("Phone", IP, FINGERPRINT),
+            vec![file_info("photo", FileType::Image, 1000)],
+        );
+        assert!(!accepted(&settings, &without_extension));
+        settings.auto_accept_extensions = vec!["png".into()];
+        assert!(!accepted(&settings, &photo_from(FINGERPRINT, IP)));
+    }
+}
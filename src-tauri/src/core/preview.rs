@@ -0,0 +1,52 @@
+use std::{io::Cursor, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{codecs::jpeg::JpegEncoder, ImageReader};
+
+use crate::models::{FileInfo, FileType};
+
+use super::utils::{MAX_PREVIEW_SIZE, THUMBNAIL_QUALITY, THUMBNAIL_SIZE};
+
+/// Drops the preview of an incoming file if it is too large or not something the frontend can
+/// show. Text files carry their content, other files may carry an image as a base64 data url.
+pub fn check_preview(file_info: &mut FileInfo) {
+    let Some(preview) = &file_info.preview else {
+        return;
+    };
+    let allowed = match file_info.file_type {
+        FileType::Text => true,
+        // anything else could make the frontend load an image from the network
+        _ => preview.starts_with("data:image/"),
+    };
+    if !allowed || preview.len() > MAX_PREVIEW_SIZE {
+        file_info.preview = None;
+    }
+}
+
+/// A small jpeg of the image at `path` as a base64 data url, `None` if it can't be decoded
+pub async fn thumbnail(path: &Path) -> Option<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let image = ImageReader::open(&path)
+            .ok()?
+            .with_guessed_format()
+            .ok()?
+            .decode()
+            .ok()?;
+        // jpeg has no alpha channel
+        let image = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+
+        let mut jpeg = Cursor::new(Vec::new());
+        image
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, THUMBNAIL_QUALITY))
+            .ok()?;
+        let preview = format!(
+            "data:image/jpeg;base64,{}",
+            STANDARD.encode(jpeg.into_inner())
+        );
+        (preview.len() <= MAX_PREVIEW_SIZE).then_some(preview)
+    })
+    .await
+    .ok()
+    .flatten()
+}
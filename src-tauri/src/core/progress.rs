@@ -0,0 +1,125 @@
+use std::{collections::HashMap, time::Instant};
+
+use crate::models::{FileInfo, FileProgressEvent, ReceiveStatus};
+
+use super::utils::PROGRESS_INTERVAL;
+
+struct FileProgress {
+    file_name: String,
+    total_bytes: u64,
+    bytes_done: u64,
+    status: ReceiveStatus,
+    started_at: Option<Instant>,
+    bytes_at_start: u64, // an upload that was resumed starts with what was kept
+    reported_at: Option<Instant>,
+}
+
+/// Turns the byte counts reported while receiving the files of a session into progress updates,
+/// at most one per `PROGRESS_INTERVAL` for each file
+pub struct ProgressTracker {
+    session_id: String,
+    files: HashMap<String, FileProgress>,
+}
+
+impl ProgressTracker {
+    pub fn new(session_id: String, files: &HashMap<String, FileInfo>) -> Self {
+        let files = files
+            .iter()
+            .map(|(file_id, file_info)| {
+                let progress = FileProgress {
+                    file_name: file_info.file_name.clone(),
+                    total_bytes: file_info.size as u64,
+                    bytes_done: 0,
+                    status: ReceiveStatus::Waiting,
+                    started_at: None,
+                    bytes_at_start: 0,
+                    reported_at: None,
+                };
+                (file_id.clone(), progress)
+            })
+            .collect();
+        Self { session_id, files }
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Starts counting the bytes of `file_id` at `offset`, which is 0 for uploads that start over
+    /// and what was kept for resumed ones. Always returns an update.
+    pub fn started(&mut self, file_id: &str, offset: u64) -> Option<FileProgressEvent> {
+        let progress = self.files.get_mut(file_id)?;
+        let now = Instant::now();
+        progress.status = ReceiveStatus::Receiving;
+        progress.started_at = Some(now);
+        progress.bytes_done = offset;
+        progress.bytes_at_start = offset;
+        progress.reported_at = Some(now);
+        Some(self.event(file_id, None))
+    }
+
+    /// Counts `len` more received bytes of `file_id`. Returns an update if the last update is
+    /// older than `PROGRESS_INTERVAL`.
+    pub fn received(&mut self, file_id: &str, len: usize) -> Option<FileProgressEvent> {
+        let progress = self.files.get_mut(file_id)?;
+        let now = Instant::now();
+        progress.bytes_done += len as u64;
+
+        let due = progress
+            .reported_at
+            .is_none_or(|reported_at| now - reported_at >= PROGRESS_INTERVAL);
+        if !due {
+            return None;
+        }
+        progress.reported_at = Some(now);
+        Some(self.event(file_id, None))
+    }
+
+    /// Marks `file_id` as waiting for the sender to resume it, always returns an update
+    pub fn interrupted(&mut self, file_id: &str) -> Option<FileProgressEvent> {
+        let progress = self.files.get_mut(file_id)?;
+        progress.status = ReceiveStatus::Waiting;
+        Some(self.event(
+            file_id,
+            Some("The upload was interrupted, waiting for the sender to resume it".into()),
+        ))
+    }
+
+    /// Marks `file_id` as done, always returns an update
+    pub fn finished(&mut self, file_id: &str, error: Option<String>) -> Option<FileProgressEvent> {
+        let progress = self.files.get_mut(file_id)?;
+        progress.status = if error.is_none() {
+            ReceiveStatus::Finished
+        } else {
+            ReceiveStatus::FinishedWithErrors
+        };
+        Some(self.event(file_id, error))
+    }
+
+    fn event(&self, file_id: &str, error: Option<String>) -> FileProgressEvent {
+        let progress = &self.files[file_id];
+        let elapsed = progress
+            .started_at
+            .map(|started_at| started_at.elapsed().as_secs_f64())
+            .unwrap_or_default();
+        let speed = if elapsed > 0.0 {
+            ((progress.bytes_done - progress.bytes_at_start) as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        let eta = (speed > 0 && progress.status == ReceiveStatus::Receiving)
+            .then(|| progress.total_bytes.saturating_sub(progress.bytes_done) / speed);
+
+        FileProgressEvent {
+            session_id: self.session_id.clone(),
+            file_id: file_id.to_string(),
+            file_name: progress.file_name.clone(),
+            bytes_done: progress.bytes_done,
+            total_bytes: progress.total_bytes,
+            speed,
+            eta,
+            status: progress.status.clone(),
+            error,
+        }
+    }
+}
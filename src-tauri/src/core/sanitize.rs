@@ -0,0 +1,188 @@
+use std::path::PathBuf;
+
+// characters that are not allowed in file names on at least one of the platforms we support
+const FORBIDDEN_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+// https://learn.microsoft.com/en-us/windows/win32/fileio/naming-a-file#naming-conventions
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turns a file name received from a peer into a relative path that is safe to join onto the
+/// destination directory.
+///
+/// Both `/` and `\` are treated as separators so that folders sent as `dir/sub/file` are kept.
+/// Drive prefixes, root and `.`/`..` segments are dropped, control and forbidden characters are
+/// stripped or replaced and reserved names are prefixed with `_`. Returns `None` if nothing is
+/// left of the name.
+pub fn sanitize_file_name(file_name: &str) -> Option<PathBuf> {
+    let file_name = file_name.replace('\\', "/");
+    let file_name = strip_drive_prefix(&file_name);
+
+    let path = file_name
+        .split('/')
+        .filter_map(sanitize_segment)
+        .collect::<PathBuf>();
+
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// The folder at the top of `file_name` once it is sanitized, `None` if the file is not in a
+/// folder
+pub fn top_level_folder(file_name: &str) -> Option<String> {
+    sanitize_file_name(file_name)
+        .filter(|path| path.components().count() > 1)
+        .and_then(|path| {
+            path.components()
+                .next()
+                .map(|top| top.as_os_str().to_string_lossy().into_owned())
+        })
+}
+
+fn strip_drive_prefix(file_name: &str) -> &str {
+    let bytes = file_name.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        &file_name[2..]
+    } else {
+        file_name
+    }
+}
+
+fn sanitize_segment(segment: &str) -> Option<String> {
+    let segment = segment
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if FORBIDDEN_CHARS.contains(&c) { '_' } else { c })
+        .collect::<String>();
+    // windows silently drops trailing dots and spaces, which would let `..` through as `.. .`
+    let segment = segment.trim().trim_end_matches(['.', ' ']);
+
+    if segment.is_empty() {
+        return None;
+    }
+
+    let stem = segment.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end()))
+    {
+        return Some(format!("_{}", segment));
+    }
+    Some(segment.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Component, Path};
+
+    use super::*;
+
+    fn sanitized(file_name: &str) -> Option<String> {
+        sanitize_file_name(file_name).map(|path| path.to_string_lossy().into_owned())
+    }
+
+    fn assert_contained(path: &Path) {
+        assert!(path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_))));
+    }
+
+    #[test]
+    fn keeps_plain_names() {
+        assert_eq!(sanitized("photo.jpg").as_deref(), Some("photo.jpg"));
+        assert_eq!(sanitized(".bashrc").as_deref(), Some(".bashrc"));
+    }
+
+    #[test]
+    fn keeps_relative_subdirectories() {
+        assert_eq!(
+            sanitize_file_name("Holiday/day1/img.jpg"),
+            Some(Path::new("Holiday").join("day1").join("img.jpg"))
+        );
+        assert_eq!(
+            sanitize_file_name("Holiday\\day1\\img.jpg"),
+            Some(Path::new("Holiday").join("day1").join("img.jpg"))
+        );
+    }
+
+    #[test]
+    fn drops_parent_segments() {
+        assert_eq!(sanitized("../../.bashrc").as_deref(), Some(".bashrc"));
+        assert_eq!(
+            sanitize_file_name("a/../../b/./c.txt"),
+            Some(Path::new("a").join("b").join("c.txt"))
+        );
+        assert_eq!(sanitized("..\\..\\evil.exe").as_deref(), Some("evil.exe"));
+        assert_eq!(sanitized(".. ./x").as_deref(), Some("x"));
+        assert_eq!(sanitized("..."), None);
+    }
+
+    #[test]
+    fn makes_absolute_paths_relative() {
+        let path = sanitize_file_name("/etc/passwd").unwrap();
+        assert_eq!(path, Path::new("etc").join("passwd"));
+        assert_contained(&path);
+
+        let path = sanitize_file_name("//server/share/file").unwrap();
+        assert_contained(&path);
+    }
+
+    #[test]
+    fn strips_drive_prefixes() {
+        let path = sanitize_file_name("C:\\Windows\\System32\\drivers").unwrap();
+        assert_eq!(path, Path::new("Windows").join("System32").join("drivers"));
+        assert_eq!(sanitized("C:evil.txt").as_deref(), Some("evil.txt"));
+    }
+
+    #[test]
+    fn removes_control_and_forbidden_characters() {
+        assert_eq!(
+            sanitized("bad\u{0}name\n.txt").as_deref(),
+            Some("badname.txt")
+        );
+        assert_eq!(
+            sanitized("what?<is>*this|.txt").as_deref(),
+            Some("what__is__this_.txt")
+        );
+        assert_eq!(sanitized("\u{7}\u{1b}"), None);
+    }
+
+    #[test]
+    fn prefixes_reserved_names() {
+        assert_eq!(sanitized("CON").as_deref(), Some("_CON"));
+        assert_eq!(sanitized("nul.txt").as_deref(), Some("_nul.txt"));
+        assert_eq!(sanitized("com1 .tar.gz").as_deref(), Some("_com1 .tar.gz"));
+        assert_eq!(
+            sanitize_file_name("dir/LPT9/file"),
+            Some(Path::new("dir").join("_LPT9").join("file"))
+        );
+        assert_eq!(sanitized("CONSOLE.txt").as_deref(), Some("CONSOLE.txt"));
+    }
+
+    #[test]
+    fn finds_top_level_folders() {
+        assert_eq!(
+            top_level_folder("Holiday/day1/img.jpg").as_deref(),
+            Some("Holiday")
+        );
+        assert_eq!(
+            top_level_folder("..\\Holiday\\img.jpg").as_deref(),
+            Some("Holiday")
+        );
+        assert_eq!(top_level_folder("/img.jpg"), None);
+        assert_eq!(top_level_folder("img.jpg"), None);
+    }
+
+    #[test]
+    fn rejects_empty_names() {
+        assert_eq!(sanitized(""), None);
+        assert_eq!(sanitized("/"), None);
+        assert_eq!(sanitized("./././"), None);
+        assert_eq!(sanitized("  "), None);
+    }
+}
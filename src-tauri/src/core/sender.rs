@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use futures_util::TryStreamExt;
+use reqwest::{header::CONTENT_LENGTH, Body, Client, Response, StatusCode};
+use tokio::{fs::File, io::AsyncSeekExt};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+use uuid::Uuid;
+
+use crate::models::{
+    DeviceInfo, FileInfo, FileType, PrepareUploadRequest, PrepareUploadResponse, RegisterRequest,
+    SendError, Sender, SenderMessage, UploadOffsetResponse,
+};
+
+use super::{
+    preview::thumbnail,
+    trust::client_tls_config,
+    utils::{sha256_file, CONNECT_TIMEOUT, MAX_UPLOAD_ATTEMPTS, RETRY_DELAY},
+};
+
+/// A file that is about to be sent and where it is read from
+struct OutgoingFile {
+    info: FileInfo,
+    source: Source,
+}
+
+enum Source {
+    Path(PathBuf),
+    Text(String), // also sent as the preview, only uploaded if the receiver doesn't show messages
+}
+
+/// What the receiver handed out for a send request, v1 receivers don't have session ids
+struct Negotiated {
+    session_id: Option<String>,
+    tokens: HashMap<String, String>,
+}
+
+pub struct SendClient {
+    client: Client,
+    info: RegisterRequest, // this device, sent along with every send request
+    pin: Option<String>,   // for receivers that require one
+    // the fingerprint every receiver announced keyed by its ip, its certificate has to match
+    fingerprints: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl SendClient {
+    pub fn new(info: RegisterRequest) -> Self {
+        // every localsend device uses a self signed certificate
+        let fingerprints = Arc::default();
+        let client = Client::builder()
+            .use_preconfigured_tls(client_tls_config(Arc::clone(&fingerprints)))
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .expect("failed to build http client");
+        Self {
+            client,
+            info,
+            pin: None,
+            fingerprints,
+        }
+    }
+
+    pub fn with_pin(mut self, pin: Option<String>) -> Self {
+        self.pin = pin;
+        self
+    }
+
+    /// Sends the files and folders at `paths` to `target`. Folders are sent with every file in
+    /// them, named `folder/sub/file` so that the receiver can recreate the folder.
+    pub async fn send_files(
+        &self,
+        target: &DeviceInfo,
+        paths: &[PathBuf],
+        progress: Sender<SenderMessage>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), SendError> {
+        let files = collect_files(paths).await?;
+        if files.is_empty() {
+            return Err(SendError::NoFiles);
+        }
+        self.send(target, files, progress, cancel_token).await
+    }
+
+    /// Sends `text` to `target` as a message, receivers that don't show messages save it as a
+    /// text file
+    pub async fn send_text(
+        &self,
+        target: &DeviceInfo,
+        text: String,
+        progress: Sender<SenderMessage>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), SendError> {
+        let info = FileInfo {
+            id: Uuid::new_v4().to_string(),
+            size: text.len(),
+            file_name: format!("{}.txt", Uuid::new_v4()),
+            file_type: FileType::Text,
+            sha256: None,
+            preview: Some(text.clone()),
+        };
+        let file = OutgoingFile {
+            info,
+            source: Source::Text(text),
+        };
+        let files = HashMap::from([(file.info.id.clone(), file)]);
+        self.send(target, files, progress, cancel_token).await
+    }
+
+    async fn send(
+        &self,
+        target: &DeviceInfo,
+        files: HashMap<String, OutgoingFile>,
+        progress: Sender<SenderMessage>,
+        cancel_token: CancellationToken,
+    ) -> Result<(), SendError> {
+        let base_url = base_url(target);
+        self.fingerprints
+            .lock()
+            .unwrap()
+            .insert(target.ip.clone(), target.fingerprint.clone());
+
+        let _ = progress.send(SenderMessage::Waiting);
+        let negotiated = tokio::select! {
+            negotiated = self.negotiate(&base_url, &files) => negotiated?,
+            _ = cancel_token.cancelled() => return Err(SendError::Cancelled),
+        };
+        let _ = progress.send(SenderMessage::Accepted(
+            negotiated
+                .tokens
+                .keys()
+                .filter_map(|file_id| files.get(file_id))
+                .map(|file| file.info.clone())
+                .collect(),
+        ));
+
+        let mut failed = Vec::new();
+        for (file_id, token) in &negotiated.tokens {
+            let Some(file) = files.get(file_id) else {
+                continue;
+            };
+            let result = tokio::select! {
+                result = self.upload(&base_url, &negotiated, file, token, &progress) => result,
+                _ = cancel_token.cancelled() => {
+                    self.cancel(&base_url, negotiated.session_id.as_deref()).await;
+                    return Err(SendError::Cancelled);
+                }
+            };
+            match result {
+                Ok(()) => {
+                    let _ = progress.send(SenderMessage::FileFinished(file_id.clone()));
+                }
+                Err(err) => {
+                    let _ = progress.send(SenderMessage::FileFailed((
+                        file_id.clone(),
+                        err.to_string(),
+                    )));
+                    failed.push((file.info.file_name.clone(), err.to_string()));
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(SendError::FilesFailed(failed))
+        }
+    }
+
+    /// Asks the receiver to accept the files. Falls back to the v1 api if the receiver doesn't
+    /// know about `/prepare-upload`.
+    async fn negotiate(
+        &self,
+        base_url: &str,
+        files: &HashMap<String, OutgoingFile>,
+    ) -> Result<Negotiated, SendError> {
+        let request = PrepareUploadRequest {
+            info: self.info.clone(),
+            files: files
+                .iter()
+                .map(|(file_id, file)| (file_id.clone(), file.info.clone()))
+                .collect(),
+        };
+
+        let pin = self.pin.as_ref().map(|pin| [("pin", pin)]);
+        let response = self
+            .client
+            .post(format!("{}/api/localsend/v2/prepare-upload", base_url))
+            .query(&pin)
+            .json(&request)
+            .send()
+            .await?;
+        if response.status() != StatusCode::NOT_FOUND {
+            let response = self.check_negotiation(response).await?;
+            if response.status() == StatusCode::NO_CONTENT {
+                // the receiver doesn't want any of the files, or it showed the text message
+                return Ok(Negotiated {
+                    session_id: None,
+                    tokens: HashMap::new(),
+                });
+            }
+            let response = response.json::<PrepareUploadResponse>().await?;
+            return Ok(Negotiated {
+                session_id: Some(response.session_id),
+                tokens: response.files,
+            });
+        }
+
+        let response = self
+            .client
+            .post(format!("{}/api/localsend/v1/send-request", base_url))
+            .query(&pin)
+            .json(&request)
+            .send()
+            .await?;
+        let response = self.check_negotiation(response).await?;
+        Ok(Negotiated {
+            session_id: None,
+            tokens: response.json().await?,
+        })
+    }
+
+    /// Uploads `file`, picking up where the upload stopped if the connection drops and the
+    /// receiver kept what it got so far
+    async fn upload(
+        &self,
+        base_url: &str,
+        negotiated: &Negotiated,
+        file: &OutgoingFile,
+        token: &str,
+        progress: &Sender<SenderMessage>,
+    ) -> Result<(), SendError> {
+        let mut offset = 0;
+        let mut attempt = 1;
+        loop {
+            match self
+                .upload_from(base_url, negotiated, file, token, offset, progress)
+                .await
+            {
+                Err(err @ (SendError::Request(_) | SendError::Status((416, _))))
+                    if attempt < MAX_UPLOAD_ATTEMPTS =>
+                {
+                    // give the receiver a moment to notice that the upload was interrupted, a
+                    // 416 means it kept a different amount than was resumed from
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    match self.upload_offset(base_url, negotiated, file, token).await {
+                        Some(kept) => offset = kept,
+                        None => return Err(err),
+                    }
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn upload_from(
+        &self,
+        base_url: &str,
+        negotiated: &Negotiated,
+        file: &OutgoingFile,
+        token: &str,
+        offset: u64,
+        progress: &Sender<SenderMessage>,
+    ) -> Result<(), SendError> {
+        let body = match &file.source {
+            Source::Path(path) => {
+                let mut source = File::open(path).await?;
+                if offset > 0 {
+                    source.seek(SeekFrom::Start(offset)).await?;
+                }
+
+                let file_id = file.info.id.clone();
+                let progress = progress.clone();
+                let mut sent = offset;
+                let stream =
+                    ReaderStream::with_capacity(source, 64 * 1024).inspect_ok(move |chunk| {
+                        sent += chunk.len() as u64;
+                        let _ = progress.send(SenderMessage::Progress((file_id.clone(), sent)));
+                    });
+                Body::wrap_stream(stream)
+            }
+            Source::Text(text) => Body::from(text.as_bytes()[offset as usize..].to_vec()),
+        };
+
+        let request = match &negotiated.session_id {
+            Some(session_id) => {
+                let mut query = vec![
+                    ("sessionId", session_id.clone()),
+                    ("fileId", file.info.id.clone()),
+                    ("token", token.to_string()),
+                ];
+                if offset > 0 {
+                    query.push(("offset", offset.to_string()));
+                }
+                self.client
+                    .post(format!("{}/api/localsend/v2/upload", base_url))
+                    .query(&query)
+            }
+            None => self
+                .client
+                .post(format!("{}/api/localsend/v1/send", base_url))
+                .query(&[("fileId", file.info.id.as_str()), ("token", token)]),
+        };
+        let response = request
+            .header(CONTENT_LENGTH, file.info.size as u64 - offset)
+            .body(body)
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(unexpected_status(response).await)
+        }
+    }
+
+    /// How much of an interrupted upload the receiver kept, `None` if it can't be resumed
+    async fn upload_offset(
+        &self,
+        base_url: &str,
+        negotiated: &Negotiated,
+        file: &OutgoingFile,
+        token: &str,
+    ) -> Option<u64> {
+        // only localsend-rs receivers keep partial uploads, and only for v2 sessions
+        let session_id = negotiated.session_id.as_ref()?;
+        let response = self
+            .client
+            .get(format!("{}/api/localsend-rs/v1/upload-offset", base_url))
+            .query(&[
+                ("sessionId", session_id.as_str()),
+                ("fileId", file.info.id.as_str()),
+                ("token", token),
+            ])
+            .send()
+            .await
+            .ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        let offset = response.json::<UploadOffsetResponse>().await.ok()?.offset;
+        // an offset past the end of the file can't be resumed from, the upload starts over
+        Some(if offset <= file.info.size as u64 {
+            offset
+        } else {
+            0
+        })
+    }
+
+    async fn check_negotiation(&self, response: Response) -> Result<Response, SendError> {
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            StatusCode::FORBIDDEN => Err(SendError::Declined),
+            StatusCode::CONFLICT => Err(SendError::Busy),
+            StatusCode::UNAUTHORIZED if self.pin.is_some() => Err(SendError::WrongPin),
+            StatusCode::UNAUTHORIZED => Err(SendError::PinRequired),
+            StatusCode::TOO_MANY_REQUESTS if self.pin.is_some() => Err(SendError::TooManyAttempts),
+            // also sent for too many text messages, the body tells which limit was hit
+            _ => Err(unexpected_status(response).await),
+        }
+    }
+
+    async fn cancel(&self, base_url: &str, session_id: Option<&str>) {
+        let request = match session_id {
+            Some(session_id) => self
+                .client
+                .post(format!("{}/api/localsend/v2/cancel", base_url))
+                .query(&[("sessionId", session_id)]),
+            None => self
+                .client
+                .post(format!("{}/api/localsend/v1/cancel", base_url)),
+        };
+        let _ = request.send().await;
+    }
+}
+
+pub(crate) fn base_url(target: &DeviceInfo) -> String {
+    let protocol = target.protocol.as_deref().unwrap_or("https");
+    if target.ip.contains(':') {
+        format!("{}://[{}]:{}", protocol, target.ip, target.port)
+    } else {
+        format!("{}://{}:{}", protocol, target.ip, target.port)
+    }
+}
+
+async fn unexpected_status(response: Response) -> SendError {
+    let status = response.status().as_u16();
+    SendError::Status((status, response.text().await.unwrap_or_default()))
+}
+
+/// Collects the files at `paths`, walking folders recursively. Files in a folder are named by
+/// their path relative to the parent of the folder, empty folders and symlinks are skipped.
+async fn collect_files(paths: &[PathBuf]) -> io::Result<HashMap<String, OutgoingFile>> {
+    let mut files = HashMap::new();
+    for path in paths {
+        let path = tokio::fs::canonicalize(path).await?;
+        if !tokio::fs::metadata(&path).await?.is_dir() {
+            let file_name = path
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let file = outgoing_file(path, file_name).await?;
+            files.insert(file.info.id.clone(), file);
+            continue;
+        }
+
+        let parent = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        let mut directories = vec![path];
+        while let Some(directory) = directories.pop() {
+            let mut entries = tokio::fs::read_dir(&directory).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    directories.push(entry.path());
+                } else if file_type.is_file() {
+                    let path = entry.path();
+                    let file_name = path
+                        .strip_prefix(&parent)
+                        .unwrap_or(&path)
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    let file = outgoing_file(path, file_name).await?;
+                    files.insert(file.info.id.clone(), file);
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+async fn outgoing_file(path: PathBuf, file_name: String) -> io::Result<OutgoingFile> {
+    let size = tokio::fs::metadata(&path).await?.len() as usize;
+    let file_type = FileType::from_path(&path);
+    let preview = match file_type {
+        FileType::Image => thumbnail(&path).await,
+        _ => None,
+    };
+    let info = FileInfo {
+        id: Uuid::new_v4().to_string(),
+        size,
+        file_name,
+        file_type,
+        sha256: Some(sha256_file(&path).await?),
+        preview,
+    };
+    Ok(OutgoingFile {
+        info,
+        source: Source::Path(path),
+    })
+}
@@ -2,60 +2,76 @@ use ::futures::{pin_mut, Stream};
 use axum_server::tls_rustls::RustlsConfig;
 use futures_util::TryStreamExt;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io,
     net::{Ipv4Addr, SocketAddr},
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use crate::models::{
-    AppState, ClientMessage, DeviceInfo, ReceiveSession, ReceiveState, ReceiveStatus,
-    RegisterRequest, SendInfo, SendRequest, Sender, ServerMessage,
+    AppState, CancelInfo, ClientMessage, ConflictPolicy, DeviceInfo, FileType, HistoryEntry,
+    Identity, PendingRequest, PinAttempts, PinInfo, PrepareUploadResponse, ReceiveSession,
+    ReceiveState, ReceiveStatus, RegisterRequest, SendInfo, SendRequest, Sender, ServerMessage,
+    Settings, TextMessages, UploadInfo, UploadOffsetResponse,
 };
 // use futures::{Stream, TryStreamExt};
 use axum::{
     body::Bytes,
-    extract::{Query, Request, State},
+    extract::{ConnectInfo, Query, Request, State},
     http::StatusCode,
+    response::{IntoResponse, Response},
     routing::{get, post},
     BoxError, Json, Router,
 };
+use indicatif::HumanBytes;
+use sha2::{Digest, Sha256};
 use tokio::{
-    fs::File,
+    fs::{File, OpenOptions},
     io::{AsyncReadExt, AsyncWriteExt, BufWriter},
-    sync::Mutex,
+    sync::{oneshot, Mutex},
 };
-use tokio_util::io::StreamReader;
-use tracing::trace;
+use tokio_util::{io::StreamReader, sync::CancellationToken};
+use tracing::{info, trace, warn};
 use uuid::Uuid;
 
-use super::utils::generate_cert;
+use super::{
+    conflict::unique_path,
+    history::{record_history, root_folder},
+    policy::{decide, space_shortage, Decision},
+    preview::check_preview,
+    sanitize::sanitize_file_name,
+    trust::admit,
+    utils::{
+        available_space, secrets_match, to_hex, MAX_PIN_ATTEMPTS, MAX_TEXT_MESSAGES,
+        PIN_BLOCK_DURATION, RESUME_GRACE_PERIOD, TEXT_MESSAGE_WINDOW,
+    },
+};
 
 pub struct Server {
-    certificate: rcgen::Certificate,
-    kp: rcgen::KeyPair,
+    identity: Identity,
     interface_addr: Ipv4Addr,
     multicast_port: u16,
 }
 
 impl Server {
-    pub fn new(interface_addr: Ipv4Addr, multicast_port: u16) -> Self {
-        let generated = generate_cert();
+    pub fn new(interface_addr: Ipv4Addr, multicast_port: u16, identity: Identity) -> Self {
         Self {
-            certificate: generated.0,
-            kp: generated.1,
+            identity,
             interface_addr,
             multicast_port,
         }
     }
 
     pub async fn start_server(&self, app_state: Arc<Mutex<AppState>>) {
-        let cert = self.certificate.pem().into_bytes();
-        let pk = self.kp.serialize_pem().into_bytes();
-        // let cert_pem = self.certificate..serialize_pem().unwrap();
-        // let private_key_pem = self.certificate.serialize_private_key_pem();
+        // reqwest pulls in ring next to the default aws-lc-rs, rustls can't pick one by itself
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let cert = self.identity.certificate_pem.clone().into_bytes();
+        let pk = self.identity.private_key_pem.clone().into_bytes();
         let config = RustlsConfig::from_pem(cert, pk).await.unwrap();
+        // kept to switch certificates when the identity is regenerated
+        app_state.lock().await.tls_config = Some(config.clone());
 
         let app = Router::new()
             .route("/api/self/devices", get(Self::get_devices))
@@ -70,14 +86,28 @@ impl Server {
             )
             .route(
                 "/api/localsend/v1/cancel",
-                post(Self::handle_cancel_request),
+                post(Self::handle_cancel_request_v1),
+            )
+            .route(
+                "/api/localsend/v2/prepare-upload",
+                post(Self::handle_prepare_upload),
+            )
+            .route("/api/localsend/v2/upload", post(Self::handle_upload))
+            // not part of the protocol, lets localsend-rs senders resume interrupted uploads
+            .route(
+                "/api/localsend-rs/v1/upload-offset",
+                get(Self::handle_upload_offset),
+            )
+            .route(
+                "/api/localsend/v2/cancel",
+                post(Self::handle_cancel_request_v2),
             )
             .with_state(app_state);
 
         let addr = SocketAddr::from((self.interface_addr, self.multicast_port));
         println!("listening on {}", addr);
         axum_server::bind_rustls(addr, config)
-            .serve(app.into_make_service())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
             .await
             .unwrap();
     }
@@ -92,146 +122,685 @@ impl Server {
     }
 
     async fn handle_register(
-        State(session_state): State<ReceiveState>,
+        State(_session_state): State<ReceiveState>,
         Json(send_request): Json<RegisterRequest>,
     ) -> Result<(), (StatusCode, String)> {
         println!("{:#?}", send_request);
         Ok(())
     }
 
-    async fn handle_cancel_request(
+    /// Cancels the sessions and pending send requests of the sender, v1 senders don't know
+    /// about session ids
+    async fn handle_cancel_request_v1(
         State(session_state): State<ReceiveState>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
     ) -> Result<(), (StatusCode, String)> {
         let mut session = session_state.lock().await;
-        if session.receive_session.is_none() {
-            // reject incoming request if another session is ongoing
+        let sender_ip = addr.ip().to_string();
+        let aborted = abort_pending_requests(&session, &sender_ip);
+        let session_ids = session
+            .receive_sessions
+            .values()
+            .filter(|receive_session| receive_session.sender.ip == sender_ip)
+            .map(|receive_session| receive_session.session_id.clone())
+            .collect::<Vec<_>>();
+        if !aborted && session_ids.is_empty() {
             return Err((
                 StatusCode::BAD_REQUEST,
                 "Cannot cancel a non existant session".into(),
             ));
         }
 
-        // TODO(notjedi): check if cancel request is valid by comparing the ip address
-        // TODO(notjedi): set session_state.receive_session to None
         // TODO(notjedi): clear buffer of sender_tx
-        let _ = session.server_tx.send(ServerMessage::CancelSession);
+        for session_id in session_ids {
+            end_session(
+                &mut session,
+                &session_id,
+                ServerMessage::CancelSession(session_id.clone()),
+            );
+        }
+        Ok(())
+    }
+
+    /// Cancels a session, only the device that started it can do so
+    async fn handle_cancel_request_v2(
+        State(session_state): State<ReceiveState>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
+        params: Query<CancelInfo>,
+    ) -> Result<(), (StatusCode, String)> {
+        let mut session = session_state.lock().await;
+        let sender_ip = addr.ip().to_string();
+        let Some(session_id) = params.0.session_id else {
+            // there is no session id yet while the user is asked to accept the request
+            if abort_pending_requests(&session, &sender_ip) {
+                return Ok(());
+            }
+            return Err((StatusCode::BAD_REQUEST, "Missing session id".into()));
+        };
+        let Some(receive_session) = session.receive_sessions.get(&session_id) else {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Cannot cancel a non existant session".into(),
+            ));
+        };
+        if receive_session.sender.ip != sender_ip {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("Invalid IP address {}", addr.ip()),
+            ));
+        }
 
-        session.receive_session = None;
+        end_session(
+            &mut session,
+            &session_id,
+            ServerMessage::CancelSession(session_id.clone()),
+        );
         Ok(())
     }
 
     async fn handle_send_request(
         State(session_state): State<ReceiveState>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
+        params: Query<PinInfo>,
         Json(send_request): Json<SendRequest>,
     ) -> Result<Json<HashMap<String, String>>, (StatusCode, String)> {
+        check_pin(
+            &mut *session_state.lock().await,
+            addr,
+            params.pin.as_deref(),
+        )?;
+        let (_, wanted_files) =
+            Self::accept_send_request(&session_state, addr, send_request).await?;
+        Ok(Json(wanted_files))
+    }
+
+    async fn handle_prepare_upload(
+        State(session_state): State<ReceiveState>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
+        params: Query<PinInfo>,
+        Json(send_request): Json<SendRequest>,
+    ) -> Result<Response, (StatusCode, String)> {
+        check_pin(
+            &mut *session_state.lock().await,
+            addr,
+            params.pin.as_deref(),
+        )?;
+        let (session_id, files) =
+            Self::accept_send_request(&session_state, addr, send_request).await?;
+        if files.is_empty() {
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+        Ok(Json(PrepareUploadResponse { session_id, files }).into_response())
+    }
+
+    /// Asks the user which files of `send_request` to receive and opens a session for them.
+    /// Returns the session id and the token of every accepted file, an empty map means the user
+    /// accepted the request but did not select any file or that the request was a text message.
+    async fn accept_send_request(
+        session_state: &ReceiveState,
+        addr: SocketAddr,
+        mut send_request: SendRequest,
+    ) -> Result<(String, HashMap<String, String>), (StatusCode, String)> {
         trace!("got request {:#?}", send_request);
+        // the ip isn't part of the request body, uploads are only accepted from this address
+        send_request.device_info.ip = addr.ip().to_string();
+        if !admit(&mut *session_state.lock().await, &send_request.device_info) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "The fingerprint doesn't match the one pinned for this device".into(),
+            ));
+        }
 
-        let mut session = session_state.lock().await;
-        if session.receive_session.is_some() {
-            // reject incoming request if another session is ongoing
-            return Err((StatusCode::CONFLICT, "Blocked by another sesssion".into()));
+        // text that is too long for a preview is received as a file instead of shown as a message
+        for file_info in send_request.files.values_mut() {
+            check_preview(file_info);
+        }
+        if let Some(text) = send_request.text_message() {
+            // messages are shown right away, there is no session and nothing to upload
+            let server_tx = {
+                let mut session = session_state.lock().await;
+                check_text_message_rate(&mut session, addr)?;
+                session.server_tx.clone()
+            };
+            let _ = server_tx.send(ServerMessage::TextMessage((send_request.device_info, text)));
+            return Ok((String::new(), HashMap::new()));
         }
 
-        let _ = session
-            .server_tx
-            .send(ServerMessage::SendRequest(send_request.clone()));
-        let response = session.client_rx.recv().await;
+        let mut file_paths = HashMap::new();
+        for (file_id, file_info) in send_request.files.iter() {
+            let Some(file_path) = sanitize_file_name(&file_info.file_name) else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid file name {:?}", file_info.file_name),
+                ));
+            };
+            file_paths.insert(file_id.clone(), file_path);
+        }
+
+        // the lock is only held to look at the state, never while waiting for the user
+        let request_id = Uuid::new_v4().to_string();
+        let cancel_token = CancellationToken::new();
+        let (settings, trusted_devices, server_tx, mut pending_guard) = {
+            let mut session = session_state.lock().await;
+            // plain localsend peers start over instead of resuming, the new request replaces the
+            // interrupted session of the sender
+            let interrupted_sessions = session
+                .receive_sessions
+                .values()
+                .filter(|receive_session| {
+                    receive_session.sender.ip == send_request.device_info.ip
+                        && !receive_session.interrupted_at.is_empty()
+                        && !receive_session
+                            .file_status
+                            .values()
+                            .any(|file_status| *file_status == ReceiveStatus::Receiving)
+                })
+                .map(|receive_session| receive_session.session_id.clone())
+                .collect::<Vec<_>>();
+            for session_id in interrupted_sessions {
+                let server_message = ServerMessage::CancelSession(session_id.clone());
+                end_session(&mut session, &session_id, server_message);
+            }
+            let mut pending_requests = session.pending_requests.lock().unwrap();
+            if session.receive_sessions.len() + pending_requests.len()
+                >= session.settings.max_sessions.max(1)
+            {
+                // reject incoming request if too many sessions are ongoing or waiting for the user
+                return Err((StatusCode::CONFLICT, "Blocked by other sessions".into()));
+            }
+            pending_requests.insert(
+                request_id.clone(),
+                PendingRequest {
+                    sender_ip: send_request.device_info.ip.clone(),
+                    cancel_token: cancel_token.clone(),
+                },
+            );
+            drop(pending_requests);
+            let pending_guard = PendingGuard {
+                pending_requests: session.pending_requests.clone(),
+                request_id,
+                server_tx: session.server_tx.clone(),
+                prompt_shown: false,
+            };
+            (
+                session.settings.clone(),
+                session.trusted_devices.clone(),
+                session.server_tx.clone(),
+                pending_guard,
+            )
+        };
+        let cancelled = || {
+            (
+                StatusCode::FORBIDDEN,
+                "The sender cancelled the request".to_string(),
+            )
+        };
+
+        let alias = send_request.device_info.alias.clone();
+        let destination_directory = settings.destination_directory(&alias, &FileType::Other);
+        let shortage = space_shortage(&settings, &alias, send_request.files.values());
+        let decision = decide(
+            &settings,
+            &trusted_devices,
+            &send_request,
+            shortage.as_ref(),
+        );
+        info!("request from {}: {}", alias, decision);
+        let response = match decision {
+            Decision::Accept(_) => Some(ClientMessage::Allow(
+                send_request.files.keys().cloned().collect(),
+            )),
+            Decision::Ask(_) => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                let _ = server_tx.send(ServerMessage::SendRequest((
+                    send_request.clone(),
+                    shortage,
+                    reply_tx,
+                )));
+                pending_guard.prompt_shown = true;
+                let prompt_timeout = Duration::from_secs(settings.prompt_timeout);
+                // the frontend is told that the question expired once the guard is dropped
+                tokio::select! {
+                    response = tokio::time::timeout(prompt_timeout, reply_rx) => match response {
+                        Ok(response) => {
+                            pending_guard.prompt_shown = false;
+                            response.ok()
+                        }
+                        Err(_) => {
+                            return Err((
+                                StatusCode::FORBIDDEN,
+                                "The request was not answered in time".into(),
+                            ))
+                        }
+                    },
+                    _ = cancel_token.cancelled() => return Err(cancelled()),
+                }
+            }
+        };
 
+        // the ids come from the user interface, only files of the request can be accepted
+        let response = response.map(|client_message| match client_message {
+            ClientMessage::Allow(mut file_ids) => {
+                file_ids.retain(|file_id| send_request.files.contains_key(file_id));
+                file_ids.sort();
+                file_ids.dedup();
+                ClientMessage::Allow(file_ids)
+            }
+            decline => decline,
+        });
         match response {
             Some(ClientMessage::Decline) | None => {
                 Err((StatusCode::FORBIDDEN, "User declined the request".into()))
             }
+            Some(ClientMessage::Allow(file_ids)) if file_ids.is_empty() => {
+                Ok((String::new(), HashMap::new()))
+            }
             Some(ClientMessage::Allow(file_ids)) => {
-                // TODO: create destination_directory if it doesn't exist
-                let state = session
-                    .receive_session
-                    .insert(ReceiveSession::new(send_request.device_info, "./".into()));
+                let mut receive_session = ReceiveSession::new(
+                    send_request.device_info,
+                    destination_directory.to_string_lossy().into_owned(),
+                    settings.max_parallel_uploads,
+                );
 
-                // TODO(notjedi): yo, why so many clones?
                 let mut wanted_files: HashMap<String, String> = HashMap::new();
-                file_ids.into_iter().for_each(|file_id| {
-                    let token = Uuid::new_v4();
-                    wanted_files.insert(file_id.clone(), token.to_string());
-                    state
+                // directory that has to exist for each file, and the directory it must stay in
+                let mut directories = HashMap::new();
+                let mut accepted_paths = Vec::new();
+                // files of other sessions are being received there, they are never overwritten
+                let mut claimed_paths = claimed_paths(&session_state.lock().await.receive_sessions);
+                for file_id in file_ids {
+                    let file_type = &send_request.files[&file_id].file_type;
+                    let directory = settings.destination_directory(&alias, file_type);
+                    let path = directory.join(&file_paths[&file_id]);
+                    // it only waits if the user is asked about a conflict
+                    pending_guard.prompt_shown = true;
+                    let path = Self::resolve_conflict(
+                        &settings,
+                        &server_tx,
+                        &cancel_token,
+                        &pending_guard.request_id,
+                        &file_id,
+                        path,
+                        &claimed_paths,
+                    )
+                    .await;
+                    pending_guard.prompt_shown = false;
+                    let Some(path) = path else {
+                        continue;
+                    };
+
+                    let token = Uuid::new_v4().to_string();
+                    wanted_files.insert(file_id.clone(), token.clone());
+                    receive_session.file_tokens.insert(file_id.clone(), token);
+                    if let Some(parent) = path.parent() {
+                        directories.insert(parent.to_path_buf(), directory.clone());
+                    }
+                    claimed_paths.insert(path.clone());
+                    receive_session.file_paths.insert(file_id.clone(), path);
+                    accepted_paths.push((file_paths[&file_id].clone(), directory));
+                    receive_session
                         .files
                         .insert(file_id.clone(), send_request.files[&file_id].clone());
-                    state.file_status.insert(file_id, ReceiveStatus::Waiting);
-                });
+                    receive_session
+                        .file_status
+                        .insert(file_id, ReceiveStatus::Waiting);
+                }
                 trace!("{:#?}", &wanted_files);
-                trace!("{:#?}, ", &state.files);
+                trace!("{:#?}, ", &receive_session.file_paths);
 
-                Ok(Json(wanted_files))
+                if wanted_files.is_empty() {
+                    // every selected file was skipped because of a conflict
+                    return Ok((String::new(), wanted_files));
+                }
+                // only the accepted files count, each in the directory it is saved in
+                if let Some(shortage) =
+                    space_shortage(&settings, &alias, receive_session.files.values())
+                {
+                    return Err((
+                        StatusCode::INSUFFICIENT_STORAGE,
+                        format!(
+                            "Not enough free space in {}, {} are needed but only {} are available",
+                            shortage.directory,
+                            HumanBytes(shortage.needed),
+                            HumanBytes(shortage.free)
+                        ),
+                    ));
+                }
+                for (directory, destination) in &directories {
+                    create_directory_within(directory, destination).await?;
+                }
+                receive_session.root_folder = root_folder(
+                    accepted_paths.iter().map(|(relative_path, directory)| {
+                        (relative_path.as_path(), directory.as_path())
+                    }),
+                    &destination_directory,
+                );
+                let session_id = receive_session.session_id.clone();
+                let files = receive_session.files.clone();
+                {
+                    // still pending until the session is stored, no request can slip in over the
+                    // limit and a cancel either aborts the request or finds the session
+                    let mut session = session_state.lock().await;
+                    if cancel_token.is_cancelled() {
+                        return Err(cancelled());
+                    }
+                    move_off_claimed_paths(&mut receive_session, &session.receive_sessions);
+                    session
+                        .receive_sessions
+                        .insert(session_id.clone(), receive_session);
+                }
+                let _ = server_tx.send(ServerMessage::SessionStarted((session_id.clone(), files)));
+                watch_idle_session(
+                    session_state.clone(),
+                    session_id.clone(),
+                    Duration::from_secs(settings.idle_timeout),
+                );
+                Ok((session_id, wanted_files))
             }
         }
     }
 
+    /// Applies the conflict policy to `path` if a file already exists there. Paths in
+    /// `claimed_paths` or with an upload on disk are always renamed around. Returns `None` if the
+    /// file should be skipped.
+    async fn resolve_conflict(
+        settings: &Settings,
+        server_tx: &Sender<ServerMessage>,
+        cancel_token: &CancellationToken,
+        request_id: &str,
+        file_id: &str,
+        path: PathBuf,
+        claimed_paths: &HashSet<PathBuf>,
+    ) -> Option<PathBuf> {
+        let is_claimed = |path: &Path| claimed_paths.contains(path) || part_path(path).exists();
+        let is_taken = |path: &Path| path.exists() || is_claimed(path);
+        if !is_taken(&path) {
+            return Some(path);
+        }
+        // another file is being received there, both are kept whatever the policy
+        if is_claimed(&path) {
+            return Some(unique_path(&path, is_taken));
+        }
+
+        let mut conflict_policy = settings.conflict_policy;
+        if conflict_policy == ConflictPolicy::Ask {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let _ = server_tx.send(ServerMessage::FileConflict((
+                request_id.into(),
+                file_id.into(),
+                path.clone(),
+                reply_tx,
+            )));
+            let prompt_timeout = Duration::from_secs(settings.prompt_timeout);
+            conflict_policy = tokio::select! {
+                response = tokio::time::timeout(prompt_timeout, reply_rx) => match response {
+                    Ok(response) => response.unwrap_or(ConflictPolicy::Skip),
+                    Err(_) => {
+                        let _ = server_tx.send(ServerMessage::PromptExpired);
+                        ConflictPolicy::Skip
+                    }
+                },
+                // the whole request is dropped by the caller, the reply is closed on return
+                _ = cancel_token.cancelled() => {
+                    let _ = server_tx.send(ServerMessage::PromptExpired);
+                    ConflictPolicy::Skip
+                }
+            };
+        }
+
+        match conflict_policy {
+            ConflictPolicy::Overwrite => Some(path),
+            ConflictPolicy::Skip => None,
+            ConflictPolicy::Rename | ConflictPolicy::Ask => Some(unique_path(&path, is_taken)),
+        }
+    }
+
     async fn handle_send_file_request(
         State(session_state): State<ReceiveState>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
         params: Query<SendInfo>,
         request: Request,
     ) -> Result<(), (StatusCode, String)> {
-        // NOTE: i shouldn't be locking session_state for the whole function but since we are only
-        // receiving files one by one, it should be fine. Shouldn't be locking for the whole
-        // function if we are going to receive multiple files at the same time.
+        let params = params.0;
+        // v1 uploads don't carry a session id, the file belongs to a session of the sender
+        let session_id = {
+            let session = session_state.lock().await;
+            let sender_ip = addr.ip().to_string();
+            let sessions = session
+                .receive_sessions
+                .values()
+                .filter(|receive_session| receive_session.sender.ip == sender_ip)
+                .collect::<Vec<_>>();
+            match sessions
+                .iter()
+                .find(|receive_session| receive_session.files.contains_key(&params.file_id))
+                .or(sessions.first())
+            {
+                // a file that none of the sessions has is turned down by `receive_file`
+                Some(receive_session) => receive_session.session_id.clone(),
+                None => {
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        "Call to /send without requesting a send".into(),
+                    ))
+                }
+            }
+        };
+        Self::receive_file(
+            &session_state,
+            addr,
+            session_id,
+            params.file_id,
+            params.token,
+            0,
+            request,
+        )
+        .await
+    }
 
-        let (file_id, path, sender) = {
-            let mut session = session_state.lock().await;
-            if session.receive_session.is_none() {
+    async fn handle_upload(
+        State(session_state): State<ReceiveState>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
+        params: Query<UploadInfo>,
+        request: Request,
+    ) -> Result<(), (StatusCode, String)> {
+        {
+            let session = session_state.lock().await;
+            if session.receive_sessions.is_empty() {
                 return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Call to /send without requesting a send".into(),
+                    StatusCode::CONFLICT,
+                    "Call to /upload without preparing an upload".into(),
+                ));
+            }
+            if !session.receive_sessions.contains_key(&params.session_id) {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!("Invalid session id {}", params.session_id),
                 ));
             }
+        }
 
-            let _ = session
-                .server_tx
-                .send(ServerMessage::SendFileRequest((params.file_id.clone(), 0)));
+        let params = params.0;
+        Self::receive_file(
+            &session_state,
+            addr,
+            params.session_id,
+            params.file_id,
+            params.token,
+            params.offset.unwrap_or(0),
+            request,
+        )
+        .await
+    }
 
-            if !session
-                .receive_session
-                .as_ref()
-                .unwrap()
-                .files
-                .contains_key(&params.file_id)
-            {
+    /// Reports how many bytes of an interrupted upload were kept so that the upload can be
+    /// continued with `/upload?offset=`
+    async fn handle_upload_offset(
+        State(session_state): State<ReceiveState>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
+        params: Query<UploadInfo>,
+    ) -> Result<Json<UploadOffsetResponse>, (StatusCode, String)> {
+        let path = {
+            let session = session_state.lock().await;
+            let Some(receive_session) = session.receive_sessions.get(&params.session_id) else {
                 return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Call to /send with unknown file id {}", params.file_id),
+                    StatusCode::FORBIDDEN,
+                    format!("Invalid session id {}", params.session_id),
                 ));
-            }
+            };
+            check_upload(
+                receive_session,
+                addr,
+                &params.file_id,
+                params.token.as_ref(),
+            )?;
+            receive_session.file_paths[&params.file_id].clone()
+        };
 
-            let receive_session = session.receive_session.as_mut().unwrap();
-            receive_session.status = ReceiveStatus::Receiving;
+        let offset = match tokio::fs::metadata(part_path(&path)).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        Ok(Json(UploadOffsetResponse { offset }))
+    }
 
-            let file_id = params.file_id.clone();
-            let path = Path::new(&receive_session.destination_directory)
-                .join(&receive_session.files[&params.file_id].file_name);
+    async fn receive_file(
+        session_state: &ReceiveState,
+        addr: SocketAddr,
+        session_id: String,
+        file_id: String,
+        token: Option<String>,
+        offset: u64,
+        request: Request,
+    ) -> Result<(), (StatusCode, String)> {
+        // the lock is only held while looking at the session, several files can be streamed at
+        // the same time, up to the limit of the session
+        let (path, size, sha256, cancel_token, upload_slots, server_tx, stall_timeout) = {
+            let mut session = session_state.lock().await;
+            let stall_timeout = Duration::from_secs(session.settings.stall_timeout);
+            let server_tx = session.server_tx.clone();
+            let Some(receive_session) = session.receive_sessions.get_mut(&session_id) else {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!("Invalid session id {}", session_id),
+                ));
+            };
+            check_upload(receive_session, addr, &file_id, token.as_ref())?;
+            if receive_session.file_status[&file_id] == ReceiveStatus::Receiving {
+                return Err((
+                    StatusCode::CONFLICT,
+                    format!("File {} is already being received", file_id),
+                ));
+            }
+            receive_session.status = ReceiveStatus::Receiving;
+            receive_session.last_activity = Instant::now();
+            receive_session
+                .file_status
+                .insert(file_id.clone(), ReceiveStatus::Receiving);
+            receive_session.interrupted_at.remove(&file_id);
 
-            println!("{:#?}", path.as_os_str());
-            (file_id, path, session.server_tx.clone())
+            let path = receive_session.file_paths[&file_id].clone();
+            trace!("receiving {} to {}", file_id, path.display());
+            (
+                path,
+                receive_session.files[&file_id].size,
+                receive_session.files[&file_id].sha256.clone(),
+                receive_session.cancel_token.clone(),
+                receive_session.upload_slots.clone(),
+                server_tx,
+                stall_timeout,
+            )
         };
 
-        let result = stream_to_file(
-            path,
-            request.into_body().into_data_stream(),
-            file_id.clone(),
-            sender,
-        )
-        .await;
+        let receive = async {
+            // uploads over the limit of the session wait here for another upload to finish
+            let _upload_slot = upload_slots.acquire().await;
+            let _ = server_tx.send(ServerMessage::FileStarted((
+                session_id.clone(),
+                file_id.clone(),
+                offset,
+            )));
+            stream_to_file(
+                part_path(&path),
+                size,
+                sha256,
+                offset,
+                request.into_body().into_data_stream(),
+                stall_timeout,
+                |len| {
+                    let _ = server_tx.send(ServerMessage::SendFileRequest((
+                        session_id.clone(),
+                        file_id.clone(),
+                        len,
+                    )));
+                },
+            )
+            .await
+        };
+        let result = tokio::select! {
+            result = receive => result,
+            _ = cancel_token.cancelled() => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "Session was cancelled while receiving file",
+            )),
+        };
+        let result = finish_upload(&path, result).await;
+        let interrupted = matches!(&result, Err(err) if err.kind() == io::ErrorKind::UnexpectedEof);
+        let wrong_offset = matches!(&result, Err(err) if err.kind() == io::ErrorKind::InvalidInput);
 
         let mut session = session_state.lock().await;
-        if session.receive_session.is_none() {
+        if matches!(&result, Err(err) if err.kind() == io::ErrorKind::TimedOut) {
+            // the sender stopped sending without closing the connection, it is not coming back
+            end_session(
+                &mut session,
+                &session_id,
+                ServerMessage::SessionTimedOut(session_id.clone()),
+            );
+            return Err((
+                StatusCode::REQUEST_TIMEOUT,
+                format!("Upload of file {} stalled", file_id),
+            ));
+        }
+        let Some(receive_session) = session.receive_sessions.get_mut(&session_id) else {
             // TODO(notjedi): should i return Ok(()) here?
             return Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Session might have been cancelled while receiving file".into(),
             ));
+        };
+        receive_session.last_activity = Instant::now();
+
+        if interrupted || wrong_offset {
+            // keep the partial file around so that the sender can resume the upload
+            let interrupted_at = Instant::now();
+            receive_session
+                .file_status
+                .insert(file_id.clone(), ReceiveStatus::Waiting);
+            receive_session
+                .interrupted_at
+                .insert(file_id.clone(), interrupted_at);
+            let _ = server_tx.send(ServerMessage::FileInterrupted((
+                session_id.clone(),
+                file_id.clone(),
+            )));
+            expire_interrupted_upload(
+                session_state.clone(),
+                session_id,
+                file_id.clone(),
+                interrupted_at,
+            );
+            return Err(match result {
+                Err(err) if wrong_offset => (StatusCode::RANGE_NOT_SATISFIABLE, err.to_string()),
+                _ => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Upload of file {} was interrupted", file_id),
+                ),
+            });
         }
-        let receive_session = session.receive_session.as_mut().unwrap();
 
         receive_session
             .file_status
-            .entry(file_id)
+            .entry(file_id.clone())
             .and_modify(|file_status| {
                 *file_status = if result.is_ok() {
                     ReceiveStatus::Finished
@@ -239,6 +808,16 @@ impl Server {
                     ReceiveStatus::FinishedWithErrors
                 }
             });
+        if let Err(err) = &result {
+            receive_session
+                .file_errors
+                .insert(file_id.clone(), err.to_string());
+        }
+        let _ = server_tx.send(ServerMessage::FileFinished((
+            session_id.clone(),
+            file_id.clone(),
+            result.as_ref().err().map(|err| err.to_string()),
+        )));
 
         let all_finished = receive_session.files.iter().all(|(file_status_id, _)| {
             receive_session.file_status[file_status_id] == ReceiveStatus::Finished
@@ -246,55 +825,711 @@ impl Server {
         });
         // TODO(notjedi): do i need to loop over everything and set the status?
         if all_finished {
-            // TODO: add support for FinishedWithErrors and send message to bin crate before
-            // setting receive_session to None
-            receive_session.status = ReceiveStatus::Finished;
-            session.receive_session = None;
+            receive_session.status = if receive_session.file_errors.is_empty() {
+                ReceiveStatus::Finished
+            } else {
+                ReceiveStatus::FinishedWithErrors
+            };
+            spawn_record_history(HistoryEntry::from_session(receive_session));
+            let _ = server_tx.send(ServerMessage::SessionFinished((
+                session_id.clone(),
+                receive_session.status.clone(),
+            )));
+            session.receive_sessions.remove(&session_id);
         }
-        Ok(())
+
+        result.map_err(|err| {
+            let status_code = match err.kind() {
+                io::ErrorKind::InvalidData => StatusCode::BAD_REQUEST,
+                io::ErrorKind::StorageFull => StatusCode::INSUFFICIENT_STORAGE,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status_code,
+                format!("Failed to receive file {}: {}", file_id, err),
+            )
+        })
     }
 }
 
+/// Paths the files of `receive_sessions` are saved to
+fn claimed_paths(receive_sessions: &HashMap<String, ReceiveSession>) -> HashSet<PathBuf> {
+    receive_sessions
+        .values()
+        .flat_map(|receive_session| receive_session.file_paths.values().cloned())
+        .collect()
+}
+
+/// Renames the files of `receive_session` that would be saved where a file of `receive_sessions`
+/// is, requests that are accepted at the same time can pick the same paths
+fn move_off_claimed_paths(
+    receive_session: &mut ReceiveSession,
+    receive_sessions: &HashMap<String, ReceiveSession>,
+) {
+    let claimed = claimed_paths(receive_sessions);
+    let mut own = receive_session
+        .file_paths
+        .values()
+        .cloned()
+        .collect::<HashSet<_>>();
+    for path in receive_session.file_paths.values_mut() {
+        if claimed.contains(path) {
+            let free = unique_path(path, |candidate| {
+                candidate.exists()
+                    || part_path(candidate).exists()
+                    || claimed.contains(candidate)
+                    || own.contains(candidate)
+            });
+            own.insert(free.clone());
+            *path = free;
+        }
+    }
+}
+
+/// Keeps a send request pending until it is dropped, which also happens when the sender gives up
+/// on the request and the handler is dropped
+struct PendingGuard {
+    pending_requests: Arc<std::sync::Mutex<HashMap<String, PendingRequest>>>,
+    request_id: String,
+    server_tx: Sender<ServerMessage>,
+    prompt_shown: bool, // the user was asked about the request and didn't answer yet
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.pending_requests
+            .lock()
+            .unwrap()
+            .remove(&self.request_id);
+        // the question that is still shown is no longer answered
+        if self.prompt_shown {
+            let _ = self.server_tx.send(ServerMessage::PromptExpired);
+        }
+    }
+}
+
+/// Aborts the send requests of `sender_ip` that are still waiting for the user. Returns whether
+/// there were any.
+fn abort_pending_requests(state: &AppState, sender_ip: &str) -> bool {
+    let pending_requests = state.pending_requests.lock().unwrap();
+    let mut aborted = false;
+    for pending_request in pending_requests.values() {
+        if pending_request.sender_ip == sender_ip {
+            pending_request.cancel_token.cancel();
+            aborted = true;
+        }
+    }
+    aborted
+}
+
+/// Checks the PIN a sender sent along with its send request if the user set one. Senders that
+/// enter too many wrong PINs are turned away until they stop trying for a while.
+fn check_pin(
+    state: &mut AppState,
+    addr: SocketAddr,
+    pin: Option<&str>,
+) -> Result<(), (StatusCode, String)> {
+    let Some(receive_pin) = state.settings.receive_pin.clone() else {
+        return Ok(());
+    };
+    if receive_pin.is_empty() {
+        return Ok(());
+    }
+    state
+        .pin_attempts
+        .retain(|_, attempts| attempts.last_failure.elapsed() < PIN_BLOCK_DURATION);
+    if state
+        .pin_attempts
+        .get(&addr.ip())
+        .is_some_and(|attempts| attempts.failures >= MAX_PIN_ATTEMPTS)
+    {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many wrong PINs, try again later".into(),
+        ));
+    }
+
+    match pin {
+        None => Err((StatusCode::UNAUTHORIZED, "PIN required".into())),
+        Some(pin) if secrets_match(pin, &receive_pin) => {
+            state.pin_attempts.remove(&addr.ip());
+            Ok(())
+        }
+        Some(_) => {
+            let attempts = state.pin_attempts.entry(addr.ip()).or_insert(PinAttempts {
+                failures: 0,
+                last_failure: Instant::now(),
+            });
+            attempts.failures += 1;
+            attempts.last_failure = Instant::now();
+            Err((StatusCode::UNAUTHORIZED, "Invalid PIN".into()))
+        }
+    }
+}
+
+/// Counts a text message of the sender at `addr`, senders that send too many in a short time are
+/// turned away until the window is over
+fn check_text_message_rate(
+    state: &mut AppState,
+    addr: SocketAddr,
+) -> Result<(), (StatusCode, String)> {
+    state
+        .text_messages
+        .retain(|_, messages| messages.window_start.elapsed() < TEXT_MESSAGE_WINDOW);
+    let messages = state
+        .text_messages
+        .entry(addr.ip())
+        .or_insert(TextMessages {
+            count: 0,
+            window_start: Instant::now(),
+        });
+    if messages.count >= MAX_TEXT_MESSAGES {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many messages, try again later".into(),
+        ));
+    }
+    messages.count += 1;
+    Ok(())
+}
+
+/// Checks that an upload of `file_id` comes from the sender of the session and carries the
+/// token that was handed out for the file
+fn check_upload(
+    receive_session: &ReceiveSession,
+    addr: SocketAddr,
+    file_id: &str,
+    token: Option<&String>,
+) -> Result<(), (StatusCode, String)> {
+    if receive_session.sender.ip != addr.ip().to_string() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Invalid IP address {}", addr.ip()),
+        ));
+    }
+    if !receive_session.files.contains_key(file_id) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown file id {}", file_id),
+        ));
+    }
+    match token {
+        None => Err((
+            StatusCode::FORBIDDEN,
+            format!("Missing token for file id {}", file_id),
+        )),
+        Some(token) if receive_session.file_tokens.get(file_id) != Some(token) => Err((
+            StatusCode::FORBIDDEN,
+            format!("Invalid token for file id {}", file_id),
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Creates `directory` and everything above it, making sure that it ends up inside
+/// `destination` even if a symlink was placed somewhere along the way
+async fn create_directory_within(
+    directory: &Path,
+    destination: &Path,
+) -> Result<(), (StatusCode, String)> {
+    let internal_error = |err: io::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Could not create {}: {}", directory.display(), err),
+        )
+    };
+    tokio::fs::create_dir_all(directory)
+        .await
+        .map_err(internal_error)?;
+
+    let directory = tokio::fs::canonicalize(directory)
+        .await
+        .map_err(internal_error)?;
+    let destination = tokio::fs::canonicalize(destination)
+        .await
+        .map_err(internal_error)?;
+    if !directory.starts_with(&destination) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!(
+                "{} is outside of {}",
+                directory.display(),
+                destination.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Removes a session before all of its files are received and tells the frontend why with
+/// `server_message`
+fn end_session(state: &mut AppState, session_id: &str, server_message: ServerMessage) {
+    if let Some(receive_session) = state.receive_sessions.remove(session_id) {
+        let _ = state.server_tx.send(server_message);
+        discard_session(receive_session);
+    }
+}
+
+/// Stops the uploads of a session that are still in flight, removes whatever was received of the
+/// files that didn't finish and records the session as cancelled
+fn discard_session(mut receive_session: ReceiveSession) {
+    receive_session.cancel_token.cancel();
+    receive_session.status = ReceiveStatus::Cancelled;
+    for (file_id, path) in &receive_session.file_paths {
+        let file_status = receive_session.file_status.get_mut(file_id).unwrap();
+        if matches!(
+            file_status,
+            ReceiveStatus::Waiting | ReceiveStatus::Receiving
+        ) {
+            *file_status = ReceiveStatus::Cancelled;
+            let _ = std::fs::remove_file(part_path(path));
+        }
+    }
+    spawn_record_history(HistoryEntry::from_session(&receive_session));
+}
+
+/// Writes `entry` to the history on a blocking thread, the caller may still hold the session
+/// state
+fn spawn_record_history(entry: HistoryEntry) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = record_history(entry) {
+            warn!("could not record session in history: {}", err);
+        }
+    });
+}
+
+/// Gives up on an interrupted upload and its session if the sender doesn't resume it within
+/// `RESUME_GRACE_PERIOD`
+fn expire_interrupted_upload(
+    session_state: ReceiveState,
+    session_id: String,
+    file_id: String,
+    interrupted_at: Instant,
+) {
+    tokio::spawn(async move {
+        tokio::time::sleep(RESUME_GRACE_PERIOD).await;
+
+        let mut session = session_state.lock().await;
+        let expired = session
+            .receive_sessions
+            .get(&session_id)
+            .is_some_and(|receive_session| {
+                receive_session.interrupted_at.get(&file_id) == Some(&interrupted_at)
+            });
+        if expired {
+            let server_message = ServerMessage::CancelSession(session_id.clone());
+            end_session(&mut session, &session_id, server_message);
+        }
+    });
+}
+
+/// Drops the session if the sender doesn't upload anything for `idle_timeout`, uploads that are
+/// in flight are watched by the stall timeout instead and interrupted ones keep it for
+/// `RESUME_GRACE_PERIOD`
+fn watch_idle_session(session_state: ReceiveState, session_id: String, idle_timeout: Duration) {
+    tokio::spawn(async move {
+        let mut wait = idle_timeout;
+        loop {
+            tokio::time::sleep(wait).await;
+
+            let mut session = session_state.lock().await;
+            let Some(receive_session) = session.receive_sessions.get(&session_id) else {
+                return;
+            };
+            let idle = receive_session.last_activity.elapsed();
+            let receiving = receive_session
+                .file_status
+                .values()
+                .any(|file_status| *file_status == ReceiveStatus::Receiving);
+            // interrupted uploads are kept until the sender had the chance to resume them
+            let resume_left = receive_session
+                .interrupted_at
+                .values()
+                .map(|interrupted_at| RESUME_GRACE_PERIOD.saturating_sub(interrupted_at.elapsed()))
+                .max()
+                .unwrap_or_default();
+            if receiving || idle < idle_timeout || !resume_left.is_zero() {
+                wait = idle_timeout
+                    .saturating_sub(idle)
+                    .max(resume_left)
+                    .max(Duration::from_secs(1));
+                continue;
+            }
+            let server_message = ServerMessage::SessionTimedOut(session_id.clone());
+            end_session(&mut session, &session_id, server_message);
+            return;
+        }
+    });
+}
+
+/// Hidden file in the destination directory that a file is written to until it is complete
+fn part_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.part", file_name))
+}
+
+/// Moves a complete upload from its `.part` file to `path`. The `.part` file of a failed upload is
+/// removed, unless the upload was interrupted and can be resumed from it.
+async fn finish_upload(path: &Path, result: io::Result<()>) -> io::Result<()> {
+    let result = match result {
+        Ok(()) => tokio::fs::rename(part_path(path), path).await,
+        Err(err) => Err(err),
+    };
+    // a resume at the wrong offset leaves what was kept alone, the sender can ask again
+    let resumable = |err: &io::Error| {
+        matches!(
+            err.kind(),
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidInput
+        )
+    };
+    if matches!(&result, Err(err) if !resumable(err)) {
+        let _ = tokio::fs::remove_file(part_path(path)).await;
+    }
+    result
+}
+
+/// Tells how far the upload got if the disk filled up while writing it, the free space is only
+/// checked before it starts
+fn write_error(err: io::Error, received: usize, size: usize) -> io::Error {
+    if err.kind() != io::ErrorKind::StorageFull {
+        return err;
+    }
+    io::Error::new(
+        io::ErrorKind::StorageFull,
+        format!(
+            "The disk filled up after {} of {}",
+            HumanBytes(received as u64),
+            HumanBytes(size as u64)
+        ),
+    )
+}
+
 // taken and modified from: https://github.com/tokio-rs/axum/blob/main/examples/stream-to-file/src/main.rs
 async fn stream_to_file<S, E>(
     path: PathBuf,
+    size: usize,
+    sha256: Option<String>,
+    offset: u64,
     stream: S,
-    file_id: String,
-    sender: Sender<ServerMessage>,
+    stall_timeout: Duration,
+    mut on_received: impl FnMut(usize), // called with the length of every chunk that is written
 ) -> std::io::Result<()>
 where
     S: Stream<Item = Result<Bytes, E>>,
     E: Into<BoxError>, // BoxError is just - Box<dyn std::error::Error + Send + Sync>
 {
-    let body_with_io_error = stream.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
+    let body_with_io_error = stream.map_err(io::Error::other);
     let body_reader = StreamReader::new(body_with_io_error);
     pin_mut!(body_reader);
 
-    let file = File::create(path).await?;
-    let mut file_buf = BufWriter::with_capacity(16384, file);
-
     // read 1024 * 16 bytes on each read call
     // can i directly write to the file buffer? rn we are copying data to a buf and writing that to the file
     let mut buf = [0u8; 16384];
+    let mut hasher = Sha256::new();
+
+    // free space might have dropped since the session was accepted
+    let remaining = size as u64 - offset.min(size as u64);
+    if let Some(free_space) = available_space(&path).filter(|free_space| *free_space < remaining) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::StorageFull,
+            format!(
+                "Not enough free space, {} are needed but only {} are available",
+                HumanBytes(remaining),
+                HumanBytes(free_space)
+            ),
+        ));
+    }
+
+    let file = if offset == 0 {
+        File::create(&path).await?
+    } else {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        let kept = file.metadata().await?.len();
+        if kept != offset {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cannot resume at {}, {} bytes were received", offset, kept),
+            ));
+        }
+        // the hash has to cover the part that was received before the upload got interrupted
+        if sha256.is_some() {
+            loop {
+                match file.read(&mut buf[..]).await? {
+                    0 => break,
+                    len => hasher.update(&buf[0..len]),
+                }
+            }
+        }
+        file
+    };
+    let mut file_buf = BufWriter::with_capacity(16384, file);
+    let mut received = offset as usize;
     loop {
-        match body_reader.read(&mut buf[..]).await {
-            Ok(0) => {
+        match tokio::time::timeout(stall_timeout, body_reader.read(&mut buf[..])).await {
+            Ok(Ok(0)) => {
                 break;
             }
-            Ok(len) => {
-                // TODO: assert len(read) == len(written)
-                // TODO: don't unwrap
+            Ok(Ok(len)) => {
+                received += len;
+                if received > size {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Received more than the declared {} bytes", size),
+                    ));
+                }
                 // TODO: no clones
-                let _ = file_buf.write(&buf[0..len]).await.unwrap();
-                let _ = sender.send(ServerMessage::SendFileRequest((file_id.clone(), len)));
+                file_buf
+                    .write_all(&buf[0..len])
+                    .await
+                    .map_err(|err| write_error(err, received, size))?;
+                hasher.update(&buf[0..len]);
+                on_received(len);
             }
-            Err(_) => {
+            Ok(Err(_)) => {
+                // keep what was received so far, the upload can be resumed from here
+                file_buf.flush().await?;
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::UnexpectedEof,
                     "Failed to read from stream",
                 ));
             }
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("No data received for {} seconds", stall_timeout.as_secs()),
+                ));
+            }
+        }
+    }
+
+    // make sure everything is on disk before the file shows up under its final name
+    file_buf
+        .flush()
+        .await
+        .map_err(|err| write_error(err, received, size))?;
+    file_buf
+        .get_ref()
+        .sync_all()
+        .await
+        .map_err(|err| write_error(err, received, size))?;
+    if received != size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Received only {} of the declared {} bytes", received, size),
+        ));
+    }
+    if let Some(sha256) = sha256 {
+        let received_sha256 = to_hex(&hasher.finalize());
+        if !received_sha256.eq_ignore_ascii_case(&sha256) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "SHA-256 mismatch, expected {} but got {}",
+                    sha256, received_sha256
+                ),
+            ));
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use crate::core::fixtures::{device_info, PHONE_FINGERPRINT, PHONE_IP};
+
+    use super::*;
+
+    /// An empty directory of its own under the temp directory
+    fn test_directory() -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("localsend-rs-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn session_saving_to(path: &Path) -> ReceiveSession {
+        let mut receive_session = ReceiveSession::new(
+            device_info("Phone", PHONE_IP, PHONE_FINGERPRINT),
+            path.parent().unwrap().to_string_lossy().into_owned(),
+            1,
+        );
+        receive_session
+            .file_paths
+            .insert("big".into(), path.to_path_buf());
+        receive_session
+    }
+
+    async fn resolve(
+        conflict_policy: ConflictPolicy,
+        path: &Path,
+        claimed_paths: &HashSet<PathBuf>,
+    ) -> Option<PathBuf> {
+        let settings = Settings {
+            conflict_policy,
+            ..Settings::default()
+        };
+        let (server_tx, _server_rx) = mpsc::unbounded_channel();
+        Server::resolve_conflict(
+            &settings,
+            &server_tx,
+            &CancellationToken::new(),
+            "request",
+            "big",
+            path.to_path_buf(),
+            claimed_paths,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn renames_around_paths_of_other_sessions() {
+        let path = test_directory().join("big.bin");
+        let claimed_paths = HashSet::from([path.clone()]);
+        let resolved = resolve(ConflictPolicy::Overwrite, &path, &claimed_paths).await;
+        assert_eq!(resolved, Some(path.with_file_name("big (1).bin")));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn renames_around_uploads_on_disk() {
+        let path = test_directory().join("big.bin");
+        std::fs::write(part_path(&path), "partial").unwrap();
+        let resolved = resolve(ConflictPolicy::Overwrite, &path, &HashSet::new()).await;
+        assert_eq!(resolved, Some(path.with_file_name("big (1).bin")));
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn moves_sessions_accepted_at_the_same_time_apart() {
+        let path = test_directory().join("big.bin");
+        let first = session_saving_to(&path);
+        let receive_sessions = HashMap::from([(first.session_id.clone(), first)]);
+        let mut second = session_saving_to(&path);
+        second
+            .file_paths
+            .insert("other".into(), path.with_file_name("big (1).bin"));
+        move_off_claimed_paths(&mut second, &receive_sessions);
+        assert_eq!(second.file_paths["big"], path.with_file_name("big (2).bin"));
+        assert_eq!(
+            second.file_paths["other"],
+            path.with_file_name("big (1).bin")
+        );
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    const HELLO_SHA256: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+    /// Streams `chunks` into the `.part` file of `path` the way an upload is received, a `None`
+    /// chunk breaks the connection
+    async fn upload(
+        path: &Path,
+        chunks: Vec<Option<&'static str>>,
+        size: usize,
+        sha256: Option<&str>,
+        offset: u64,
+    ) -> io::Result<()> {
+        let stream = futures_util::stream::iter(chunks.into_iter().map(|chunk| match chunk {
+            Some(chunk) => Ok(Bytes::from_static(chunk.as_bytes())),
+            None => Err(io::Error::from(io::ErrorKind::ConnectionReset)),
+        }));
+        let result = stream_to_file(
+            part_path(path),
+            size,
+            sha256.map(String::from),
+            offset,
+            stream,
+            Duration::from_secs(5),
+            |_| {},
+        )
+        .await;
+        finish_upload(path, result).await
+    }
+
+    #[tokio::test]
+    async fn renames_the_part_file_once_complete() {
+        let path = test_directory().join("hello.txt");
+        let chunks = vec![Some("hello "), Some("world")];
+        upload(&path, chunks, 11, Some(HELLO_SHA256), 0)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+        assert!(!part_path(&path).exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_a_short_body() {
+        let path = test_directory().join("hello.txt");
+        let err = upload(&path, vec![Some("hello")], 11, None, 0)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_a_body_longer_than_declared() {
+        let path = test_directory().join("hello.txt");
+        let err = upload(&path, vec![Some("hello world")], 5, None, 0)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_a_sha256_mismatch() {
+        let path = test_directory().join("hello.txt");
+        let chunks = vec![Some("hello there")];
+        let err = upload(&path, chunks, 11, Some(HELLO_SHA256), 0)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("SHA-256 mismatch"));
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_interrupted_uploads_to_resume_them() {
+        let path = test_directory().join("hello.txt");
+        let chunks = vec![Some("hello "), None];
+        let err = upload(&path, chunks, 11, Some(HELLO_SHA256), 0)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(std::fs::read_to_string(part_path(&path)).unwrap(), "hello ");
+
+        // the hash covers the part that was received before the interruption
+        upload(&path, vec![Some("world")], 11, Some(HELLO_SHA256), 6)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+        assert!(!part_path(&path).exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_to_resume_at_a_wrong_offset() {
+        let path = test_directory().join("hello.txt");
+        std::fs::write(part_path(&path), "hello").unwrap();
+        let err = upload(&path, vec![Some("world")], 11, None, 6)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // what was kept is left alone, the sender can ask for the offset again
+        assert_eq!(std::fs::read_to_string(part_path(&path)).unwrap(), "hello");
+        assert!(!path.exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use std::{collections::HashMap, io, sync::Arc};
+
+use tokio::sync::{mpsc, watch, Mutex};
+
+use crate::models::{
+    AppState, DeviceInfo, Identity, LocalSendDevice, ReceiveState, Receiver, ServerMessage,
+    Settings,
+};
+
+use super::{
+    server::Server,
+    trust::{admit, load_trusted_devices},
+    utils::{INTERFACE_ADDR, MULTICAST_ADDR, MULTICAST_PORT},
+};
+
+/// Creates the state of a device called `alias`. Returns the state along with the receiving end
+/// of the messages the server sends while receiving, questions come with their own reply channel.
+pub fn app_state(alias: &str, settings: Settings) -> (ReceiveState, Receiver<ServerMessage>) {
+    let identity = Identity::load();
+    let device = LocalSendDevice::new(
+        alias.to_string(),
+        identity.fingerprint.clone(),
+        INTERFACE_ADDR,
+        MULTICAST_ADDR,
+        MULTICAST_PORT,
+    );
+    let (server_tx, server_rx) = mpsc::unbounded_channel();
+    let (announced_device, _) = watch::channel(device.this_device.clone());
+    let app_state = Arc::new(Mutex::new(AppState {
+        device,
+        announced_device,
+        server_tx,
+        pending_requests: Arc::default(),
+        receive_sessions: HashMap::new(),
+        pin_attempts: HashMap::new(),
+        text_messages: HashMap::new(),
+        identity,
+        tls_config: None,
+        trusted_devices: load_trusted_devices(),
+        settings,
+    }));
+    (app_state, server_rx)
+}
+
+/// Announces the device and keeps the list of nearby devices in `app_state` up to date
+pub fn spawn_discovery(app_state: &ReceiveState) {
+    let (tx_task, rx_task) = mpsc::channel::<Vec<DeviceInfo>>(1000);
+
+    let devices_app_state = app_state.clone();
+    tokio::spawn(async move {
+        let mut receiver = rx_task;
+        while let Some(incoming_event) = receiver.recv().await {
+            let mut state = devices_app_state.lock().await;
+            let devices = incoming_event
+                .into_iter()
+                .filter(|device_info| admit(&mut state, device_info))
+                .collect();
+            state.device.devices = devices;
+        }
+    });
+
+    let device_app_state = app_state.clone();
+    tokio::spawn(async move {
+        let app_state = device_app_state.lock().await;
+        let mut device = app_state.device.clone();
+        let announced_device = app_state.announced_device.subscribe();
+        drop(app_state);
+        device.connect().await;
+        device
+            .listen_and_announce_multicast(
+                device.socket.clone().unwrap(),
+                announced_device,
+                tx_task,
+            )
+            .await;
+    });
+}
+
+pub fn spawn_server(app_state: &ReceiveState) {
+    let app_state = app_state.clone();
+    tokio::spawn(async move {
+        let identity = app_state.lock().await.identity.clone();
+        let server = Server::new(INTERFACE_ADDR, MULTICAST_PORT, identity);
+        server.start_server(app_state).await;
+    });
+}
+
+/// Gives this device a new identity, a running server switches to the new certificate right
+/// away. Returns the new fingerprint.
+pub async fn regenerate_identity(app_state: &ReceiveState) -> io::Result<String> {
+    let identity = Identity::regenerate()?;
+    let mut state = app_state.lock().await;
+    if let Some(tls_config) = &state.tls_config {
+        tls_config
+            .reload_from_pem(
+                identity.certificate_pem.clone().into_bytes(),
+                identity.private_key_pem.clone().into_bytes(),
+            )
+            .await?;
+    }
+    state.device.this_device.device_info.fingerprint = identity.fingerprint.clone();
+    // discovery has to announce the fingerprint of the certificate that is served now
+    state
+        .announced_device
+        .send_replace(state.device.this_device.clone());
+    state.identity = identity;
+    Ok(state.identity.fingerprint.clone())
+}
@@ -0,0 +1,78 @@
+use std::{fs, io, path::PathBuf};
+
+use chrono::Local;
+
+use crate::models::{ConflictPolicy, FileType, ReceivePolicy, Settings};
+
+use super::{sanitize::sanitize_file_name, utils::APP_DIR_NAME};
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            download_directory: dirs::download_dir().unwrap_or_else(|| PathBuf::from("./")),
+            session_subfolders: false,
+            route_by_file_type: false,
+            conflict_policy: ConflictPolicy::default(),
+            prompt_timeout: 60,
+            idle_timeout: 120,
+            stall_timeout: 30,
+            max_sessions: 3,
+            max_parallel_uploads: 3,
+            receive_policy: ReceivePolicy::default(),
+            favorite_devices: vec![],
+            auto_accept_max_size: None,
+            auto_accept_file_types: vec![],
+            auto_accept_extensions: vec![],
+            receive_pin: None,
+            block_untrusted_devices: false,
+        }
+    }
+}
+
+impl Settings {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config_dir| config_dir.join(APP_DIR_NAME).join("settings.json"))
+    }
+
+    /// Reads the persisted settings, falls back to the defaults for anything that is missing
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|settings| serde_json::from_str(&settings).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "No config directory on this system",
+            )
+        })?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Directory that a file of `file_type` sent by `alias` is saved in. The directory might not
+    /// exist yet.
+    pub fn destination_directory(&self, alias: &str, file_type: &FileType) -> PathBuf {
+        let typed_directory = match file_type {
+            FileType::Image if self.route_by_file_type => dirs::picture_dir(),
+            FileType::Video if self.route_by_file_type => dirs::video_dir(),
+            _ => None,
+        };
+        let directory = typed_directory.unwrap_or_else(|| self.download_directory.clone());
+
+        if !self.session_subfolders {
+            return directory;
+        }
+        // the alias is chosen by the sender, so it must not be able to add more than one level
+        let alias = sanitize_file_name(&alias.replace(['/', '\\'], "_"))
+            .unwrap_or_else(|| PathBuf::from("unknown"));
+        directory
+            .join(alias)
+            .join(Local::now().format("%Y-%m-%d").to_string())
+    }
+}
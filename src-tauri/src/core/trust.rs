@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Local;
+use reqwest::{tls::TlsInfo, Client};
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::models::{AppState, DeviceInfo, SendError, ServerMessage, TrustedDevice};
+
+use super::{
+    sender::base_url,
+    utils::{to_hex, APP_DIR_NAME, CONNECT_TIMEOUT},
+};
+
+/// How a device compares to the pinned devices
+pub enum Trust {
+    Known(usize), // index of its pin
+    New,
+    NewAtPinnedIp(TrustedDevice), // a new device at the ip of this pin, only worth a warning
+    Mismatch(TrustedDevice),      // the pin of the alias it showed up with
+}
+
+/// Looks up the pin of `device_info` by its fingerprint, a device with an unknown fingerprint
+/// that uses the alias of a pinned device is a mismatch. Ips are handed out again to other
+/// devices, so a new fingerprint at a pinned ip is only reported, and v1 devices don't have a
+/// fingerprint to compare.
+pub fn check(trusted_devices: &[TrustedDevice], device_info: &DeviceInfo) -> Trust {
+    if device_info.fingerprint.is_empty() {
+        return Trust::New;
+    }
+    let known = trusted_devices
+        .iter()
+        .position(|trusted_device| trusted_device.fingerprint == device_info.fingerprint);
+    if let Some(index) = known {
+        return Trust::Known(index);
+    }
+    let pinned = trusted_devices
+        .iter()
+        .find(|trusted_device| trusted_device.alias == device_info.alias);
+    if let Some(pinned) = pinned {
+        return Trust::Mismatch(pinned.clone());
+    }
+    let pinned_ip = trusted_devices
+        .iter()
+        .find(|trusted_device| trusted_device.ip == device_info.ip);
+    match pinned_ip {
+        Some(pinned) => Trust::NewAtPinnedIp(pinned.clone()),
+        None => Trust::New,
+    }
+}
+
+/// Checks `device_info` against the pinned devices and pins it if it is seen for the first time.
+/// Returns false if it has to be kept out because it doesn't match a pin and the settings block
+/// such devices.
+pub fn admit(state: &mut AppState, device_info: &DeviceInfo) -> bool {
+    match check(&state.trusted_devices, device_info) {
+        // announcements and requests can claim any fingerprint, they don't move a pin
+        Trust::Known(_) => true,
+        Trust::New => {
+            pin(state, device_info);
+            true
+        }
+        Trust::NewAtPinnedIp(pinned) => {
+            // the ip may just have been handed out again, the device is let in but reported
+            warn!(
+                "{} ({}) has the fingerprint {} but {} was pinned to {} at that ip",
+                device_info.alias,
+                device_info.ip,
+                device_info.fingerprint,
+                pinned.alias,
+                pinned.fingerprint
+            );
+            let _ = state.server_tx.send(ServerMessage::UntrustedDevice((
+                device_info.clone(),
+                pinned,
+                false,
+            )));
+            pin(state, device_info);
+            true
+        }
+        Trust::Mismatch(pinned) => {
+            let blocked = state.settings.block_untrusted_devices;
+            warn!(
+                "{} ({}) has the fingerprint {:?} but {} ({}) was pinned to {}{}",
+                device_info.alias,
+                device_info.ip,
+                device_info.fingerprint,
+                pinned.alias,
+                pinned.ip,
+                pinned.fingerprint,
+                if blocked { ", it is blocked" } else { "" }
+            );
+            let _ = state.server_tx.send(ServerMessage::UntrustedDevice((
+                device_info.clone(),
+                pinned,
+                blocked,
+            )));
+            !blocked
+        }
+    }
+}
+
+fn pin(state: &mut AppState, device_info: &DeviceInfo) {
+    // v1 devices don't have a fingerprint to pin
+    if device_info.fingerprint.is_empty() {
+        return;
+    }
+    info!(
+        "pinned {} ({}) to {}",
+        device_info.alias, device_info.ip, device_info.fingerprint
+    );
+    state.trusted_devices.push(TrustedDevice {
+        fingerprint: device_info.fingerprint.clone(),
+        alias: device_info.alias.clone(),
+        ip: device_info.ip.clone(),
+        first_seen: Local::now().to_rfc3339(),
+    });
+    save(&state.trusted_devices);
+}
+
+/// Moves the pin of `device_info` to the alias and ip it uses now. Only for devices whose
+/// certificate was checked against their fingerprint on a connection to them.
+pub fn update_pin(state: &mut AppState, device_info: &DeviceInfo) {
+    // without https there is no certificate that was checked
+    if device_info.protocol.as_deref() == Some("http") {
+        return;
+    }
+    if let Trust::Known(index) = check(&state.trusted_devices, device_info) {
+        let trusted_device = &mut state.trusted_devices[index];
+        if trusted_device.alias != device_info.alias || trusted_device.ip != device_info.ip {
+            trusted_device.alias = device_info.alias.clone();
+            trusted_device.ip = device_info.ip.clone();
+            save(&state.trusted_devices);
+        }
+    }
+}
+
+/// Removes the pins of the devices with `name` as fingerprint, alias or ip. Returns the removed
+/// pins.
+pub fn forget(trusted_devices: &mut Vec<TrustedDevice>, name: &str) -> Vec<TrustedDevice> {
+    let (forgotten, kept) = trusted_devices.drain(..).partition(|trusted_device| {
+        trusted_device.fingerprint == name
+            || trusted_device.alias == name
+            || trusted_device.ip == name
+    });
+    *trusted_devices = kept;
+    forgotten
+}
+
+fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|data_dir| data_dir.join(APP_DIR_NAME).join("trusted_devices.json"))
+}
+
+pub fn load_trusted_devices() -> Vec<TrustedDevice> {
+    path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|trusted_devices| serde_json::from_str(&trusted_devices).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_trusted_devices(trusted_devices: &[TrustedDevice]) -> io::Result<()> {
+    let path = path().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "No data directory on this system")
+    })?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(trusted_devices)?)
+}
+
+fn save(trusted_devices: &[TrustedDevice]) {
+    if let Err(err) = save_trusted_devices(trusted_devices) {
+        warn!("could not save the pinned devices: {}", err);
+    }
+}
+
+/// Connects to `target` and returns the fingerprint of the certificate it serves, `None` if it
+/// doesn't use https
+pub async fn certificate_fingerprint(target: &DeviceInfo) -> Result<Option<String>, SendError> {
+    let client = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()?;
+    // any response will do, the certificate is all that is needed
+    let response = client
+        .get(format!("{}/api/localsend/v2/info", base_url(target)))
+        .send()
+        .await?;
+    Ok(response
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(|tls_info| tls_info.peer_certificate())
+        .map(|certificate| to_hex(&Sha256::digest(certificate))))
+}
+
+/// Tls config of the send client. Receivers present self signed certificates, one is accepted
+/// only if its fingerprint is the one in `fingerprints` for the ip of the receiver. Receivers
+/// without a fingerprint there are accepted with any certificate.
+pub(crate) fn client_tls_config(fingerprints: Arc<Mutex<HashMap<String, String>>>) -> ClientConfig {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("the default protocol versions are supported")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(FingerprintVerifier {
+            fingerprints,
+            provider,
+        }))
+        .with_no_client_auth()
+}
+
+/// Whether a request failed because the certificate of the receiver didn't match its fingerprint
+pub(crate) fn is_fingerprint_mismatch(err: &reqwest::Error) -> bool {
+    let mut source: Option<&(dyn Error + 'static)> = Some(err);
+    while let Some(err) = source {
+        if matches!(
+            err.downcast_ref::<rustls::Error>(),
+            Some(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure
+            ))
+        ) {
+            return true;
+        }
+        // io errors leave the error they wrap out of the chain
+        source = match err.downcast_ref::<io::Error>() {
+            Some(err) => err.get_ref().map(|err| err as &(dyn Error + 'static)),
+            None => err.source(),
+        };
+    }
+    false
+}
+
+#[derive(Debug)]
+struct FingerprintVerifier {
+    fingerprints: Arc<Mutex<HashMap<String, String>>>, // keyed by ip
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprints = self.fingerprints.lock().unwrap();
+        match fingerprints.get(server_name.to_str().as_ref()) {
+            Some(fingerprint)
+                if !fingerprint.is_empty()
+                    && *fingerprint != to_hex(&Sha256::digest(end_entity)) =>
+            {
+                Err(rustls::Error::InvalidCertificate(
+                    CertificateError::ApplicationVerificationFailure,
+                ))
+            }
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    // the receiver still has to prove that it owns the certificate
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::fixtures::{device_info, pinned};
+
+    use super::*;
+
+    #[test]
+    fn known_by_fingerprint_wherever_it_is() {
+        let trusted_devices = pinned();
+        let moved = device_info("Renamed laptop", "192.168.1.99", "ddeeff");
+        assert!(matches!(check(&trusted_devices, &moved), Trust::Known(1)));
+    }
+
+    #[test]
+    fn new_fingerprint_with_a_new_alias_is_new() {
+        let trusted_devices = pinned();
+        let tablet = device_info("Tablet", "192.168.1.30", "112233");
+        assert!(matches!(check(&trusted_devices, &tablet), Trust::New));
+    }
+
+    #[test]
+    fn new_fingerprint_with_a_pinned_alias_is_a_mismatch() {
+        let trusted_devices = pinned();
+        let impostor = device_info("Phone", "192.168.1.30", "112233");
+        match check(&trusted_devices, &impostor) {
+            Trust::Mismatch(pin) => assert_eq!(pin.fingerprint, "aabbcc"),
+            _ => panic!("expected a mismatch"),
+        }
+    }
+
+    #[test]
+    fn new_fingerprint_at_a_pinned_ip_is_reported() {
+        // ips are handed out again, another device may get the one of a pinned device
+        let trusted_devices = pinned();
+        let other = device_info("Tablet", "192.168.1.20", "112233");
+        match check(&trusted_devices, &other) {
+            Trust::NewAtPinnedIp(pin) => assert_eq!(pin.fingerprint, "aabbcc"),
+            _ => panic!("expected a new device at a pinned ip"),
+        }
+    }
+
+    #[test]
+    fn missing_fingerprint_is_never_a_mismatch() {
+        let trusted_devices = pinned();
+        let v1_device = device_info("Phone", "192.168.1.20", "");
+        assert!(matches!(check(&trusted_devices, &v1_device), Trust::New));
+    }
+
+    #[test]
+    fn forgets_by_fingerprint() {
+        let mut trusted_devices = pinned();
+        let forgotten = forget(&mut trusted_devices, "aabbcc");
+        assert_eq!(forgotten.len(), 1);
+        assert_eq!(forgotten[0].alias, "Phone");
+        assert_eq!(trusted_devices.len(), 1);
+        assert_eq!(trusted_devices[0].alias, "Laptop");
+    }
+
+    #[test]
+    fn forgets_by_alias() {
+        let mut trusted_devices = pinned();
+        let forgotten = forget(&mut trusted_devices, "Laptop");
+        assert_eq!(forgotten.len(), 1);
+        assert_eq!(forgotten[0].fingerprint, "ddeeff");
+        assert_eq!(trusted_devices.len(), 1);
+    }
+
+    #[test]
+    fn forgets_by_ip() {
+        let mut trusted_devices = pinned();
+        let forgotten = forget(&mut trusted_devices, "192.168.1.20");
+        assert_eq!(forgotten.len(), 1);
+        assert_eq!(forgotten[0].fingerprint, "aabbcc");
+        assert_eq!(trusted_devices.len(), 1);
+    }
+
+    #[test]
+    fn forgets_nothing_for_an_unknown_name() {
+        let mut trusted_devices = pinned();
+        assert!(forget(&mut trusted_devices, "Tablet").is_empty());
+        assert_eq!(trusted_devices.len(), 2);
+    }
+}
@@ -1,19 +1,70 @@
-use std::{error::Error, fs::File, io::Write, net::{IpAddr, Ipv4Addr}, process::Command};
+use std::{
+    error::Error,
+    fs::File,
+    io::Write,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    process::Command,
+    time::Duration,
+};
 
 use futures_util::future;
 use network_interface::{NetworkInterface, NetworkInterfaceConfig};
 use rcgen::{Certificate, CertificateParams, DnType, DnValue, KeyPair};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+use tracing_subscriber::EnvFilter;
 
 pub const BUFFER_SIZE: u16 = 2048;
 pub const NUM_REPEAT: u8 = 2;
 pub const DEVICE_MODEL: &str = "linux";
 pub const DEVICE_TYPE: &str = "desktop";
+pub const PROTOCOL_VERSION: &str = "2.0";
+pub const PROTOCOL: &str = "https";
 
 pub const ALIAS: &str = "rustsend";
+pub const APP_DIR_NAME: &str = "localsend-rs";
 pub const INTERFACE_ADDR: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 0);
 pub const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 167);
 pub const MULTICAST_PORT: u16 = 53317;
 
+/// How long the partial file and session of an interrupted upload are kept for the sender to
+/// resume it
+pub const RESUME_GRACE_PERIOD: Duration = Duration::from_secs(120);
+pub const MAX_HISTORY_ENTRIES: usize = 200;
+/// Longest preview of an incoming file that is kept, in bytes. Text messages that are longer are
+/// received as files.
+pub const MAX_PREVIEW_SIZE: usize = 64 * 1024;
+/// Largest width and height of the thumbnails sent along with images
+pub const THUMBNAIL_SIZE: u32 = 128;
+pub const THUMBNAIL_QUALITY: u8 = 70;
+/// How often the desktop app is told about the progress of each file
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// Wrong PINs a sender may enter before it gets 429 responses, until it stops trying for
+/// `PIN_BLOCK_DURATION`
+pub const MAX_PIN_ATTEMPTS: u32 = 5;
+pub const PIN_BLOCK_DURATION: Duration = Duration::from_secs(60);
+/// Text messages a sender may send within `TEXT_MESSAGE_WINDOW` before it gets 429 responses,
+/// they don't take a session and are shown right away
+pub const MAX_TEXT_MESSAGES: u32 = 5;
+pub const TEXT_MESSAGE_WINDOW: Duration = Duration::from_secs(60);
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often an upload is attempted before a file is given up on, attempts after the first one
+/// resume the upload
+pub const MAX_UPLOAD_ATTEMPTS: u32 = 3;
+pub const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Logs to stderr, `RUST_LOG` overrides the default of logging what this crate does at info level
+pub fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("localsend_rs_lib=info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
 pub fn get_current_device_ips() -> Vec<IpAddr> {
     let mut my_ips: Vec<IpAddr> = vec![];
     for network_interface in NetworkInterface::show().unwrap_or(vec![]).iter() {
@@ -153,3 +204,41 @@ pub fn generate_cert() -> (Certificate, KeyPair) {
     let key_pair = KeyPair::generate().unwrap();
     (params.self_signed(&key_pair).unwrap(), key_pair)
 }
+
+/// Free space of the filesystem `path` is on, `path` doesn't have to exist yet
+pub fn available_space(path: &Path) -> Option<u64> {
+    path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .find(|ancestor| ancestor.exists())
+        .or(Some(Path::new(".")))
+        .and_then(|existing| fs4::available_space(existing).ok())
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Compares two secrets in a time that doesn't depend on where they differ. The SHA-256 of both
+/// is compared, which also hides the length of the secret.
+pub fn secrets_match(a: &str, b: &str) -> bool {
+    Sha256::digest(a)
+        .iter()
+        .zip(Sha256::digest(b).iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// Hex encoded SHA-256 of the file at `path`, sent along with the file metadata so that the
+/// receiver can verify the file
+pub async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 16384];
+    loop {
+        match file.read(&mut buf).await? {
+            0 => break,
+            len => hasher.update(&buf[..len]),
+        }
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_shell::ShellExt;
+use tokio::sync::{mpsc, Mutex};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    core::{
+        history::{load_history, load_history_with_previews},
+        progress::ProgressTracker,
+        sanitize::top_level_folder,
+        sender::SendClient,
+        service::{self, app_state, spawn_discovery, spawn_server},
+        trust::{forget, save_trusted_devices, update_pin},
+        utils::{init_tracing, ALIAS},
+    },
+    models::{
+        AppState, ClientMessage, ConflictPolicy, DeviceInfo, FavoriteDevice, FileConflictEvent,
+        FileFolder, HistoryEntry, ReceiveStatus, Receiver, Reply, SendRequestEvent, ServerMessage,
+        SessionEvent, SessionEventKind, Settings, TextMessageEvent, TrustedDevice,
+        UntrustedDeviceEvent,
+    },
+};
+
+const SEND_REQUEST_EVENT: &str = "send-request";
+const FILE_CONFLICT_EVENT: &str = "file-conflict";
+const TEXT_MESSAGE_EVENT: &str = "text-message";
+const SESSION_EVENT: &str = "receive-session";
+const FILE_PROGRESS_EVENT: &str = "receive-progress";
+const PROMPT_EXPIRED_EVENT: &str = "prompt-expired";
+const UNTRUSTED_DEVICE_EVENT: &str = "untrusted-device";
+
+/// Questions of the server that are shown to the user, along with where to send the answer
+#[derive(Default)]
+struct Prompts {
+    pending_requests: Mutex<HashMap<String, Reply<ClientMessage>>>, // keyed by the request id
+    pending_conflicts: Mutex<HashMap<String, Reply<ConflictPolicy>>>, // keyed by the conflict id
+}
+
+impl Prompts {
+    /// Forgets the questions the server stopped waiting for. Returns their request and conflict
+    /// ids.
+    async fn remove_expired(&self) -> Vec<String> {
+        let mut expired = Vec::new();
+        self.pending_requests
+            .lock()
+            .await
+            .retain(|request_id, reply| {
+                if reply.is_closed() {
+                    expired.push(request_id.clone());
+                }
+                !reply.is_closed()
+            });
+        self.pending_conflicts
+            .lock()
+            .await
+            .retain(|conflict_id, reply| {
+                if reply.is_closed() {
+                    expired.push(conflict_id.clone());
+                }
+                !reply.is_closed()
+            });
+        expired
+    }
+}
+
+#[tauri::command]
+async fn get_nearby_devices(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<Vec<DeviceInfo>, ()> {
+    let state = state.lock().await;
+    let devices = state.device.devices.clone();
+    Ok(devices)
+}
+
+#[tauri::command]
+async fn get_settings(state: tauri::State<'_, Arc<Mutex<AppState>>>) -> Result<Settings, ()> {
+    let state = state.lock().await;
+    Ok(state.settings.clone())
+}
+
+#[tauri::command]
+async fn update_settings(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+    settings: Settings,
+) -> Result<(), String> {
+    settings.save().map_err(|err| err.to_string())?;
+    let mut state = state.lock().await;
+    state.settings = settings;
+    Ok(())
+}
+
+/// Adds the device with `fingerprint` to the favorite devices, or removes it if `favorite` is false
+#[tauri::command]
+async fn set_favorite_device(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+    fingerprint: String,
+    alias: String,
+    favorite: bool,
+) -> Result<Settings, String> {
+    let mut state = state.lock().await;
+    let mut settings = state.settings.clone();
+    settings
+        .favorite_devices
+        .retain(|device| device.fingerprint != fingerprint);
+    if favorite {
+        settings
+            .favorite_devices
+            .push(FavoriteDevice { fingerprint, alias });
+    }
+    settings.save().map_err(|err| err.to_string())?;
+    state.settings = settings.clone();
+    Ok(settings)
+}
+
+#[tauri::command]
+async fn get_fingerprint(state: tauri::State<'_, Arc<Mutex<AppState>>>) -> Result<String, ()> {
+    let state = state.lock().await;
+    Ok(state.identity.fingerprint.clone())
+}
+
+/// Replaces the certificate of this device, returns the new fingerprint
+#[tauri::command]
+async fn regenerate_identity(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<String, String> {
+    service::regenerate_identity(state.inner())
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn get_trusted_devices(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+) -> Result<Vec<TrustedDevice>, ()> {
+    let state = state.lock().await;
+    Ok(state.trusted_devices.clone())
+}
+
+/// Forgets the pin of the device with `fingerprint`, it is pinned again the next time it is seen
+#[tauri::command]
+async fn forget_trusted_device(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+    fingerprint: String,
+) -> Result<Vec<TrustedDevice>, String> {
+    let mut state = state.lock().await;
+    forget(&mut state.trusted_devices, &fingerprint);
+    save_trusted_devices(&state.trusted_devices).map_err(|err| err.to_string())?;
+    Ok(state.trusted_devices.clone())
+}
+
+#[tauri::command]
+async fn get_history() -> Result<Vec<HistoryEntry>, ()> {
+    Ok(load_history_with_previews())
+}
+
+#[tauri::command]
+async fn open_history_folder(app: tauri::AppHandle, session_id: String) -> Result<(), String> {
+    let folder = load_history()
+        .into_iter()
+        .find(|entry| entry.session_id == session_id)
+        .and_then(|entry| entry.folder)
+        .ok_or_else(|| format!("No folder was received in session {}", session_id))?;
+    #[allow(deprecated)]
+    let opened = app.shell().open(folder.to_string_lossy(), None);
+    opened.map_err(|err| err.to_string())
+}
+
+/// Sends `text` as a message to the nearby device with `fingerprint`
+#[tauri::command]
+async fn send_text(
+    state: tauri::State<'_, Arc<Mutex<AppState>>>,
+    fingerprint: String,
+    text: String,
+    pin: Option<String>,
+) -> Result<(), String> {
+    // the ip of a device isn't passed to the frontend, the device is looked up again
+    let (device, info) = {
+        let state = state.lock().await;
+        let device = state
+            .device
+            .devices
+            .iter()
+            .find(|device| device.fingerprint == fingerprint)
+            .cloned()
+            .ok_or_else(|| format!("No nearby device {}", fingerprint))?;
+        (device, state.device.register_request())
+    };
+    // a message has no progress worth showing
+    let (progress_tx, _) = mpsc::unbounded_channel();
+    let result = SendClient::new(info)
+        .with_pin(pin.filter(|pin| !pin.is_empty()))
+        .send_text(&device, text, progress_tx, CancellationToken::new())
+        .await;
+    if result.is_ok() {
+        // the certificate of the device matched its fingerprint
+        update_pin(&mut *state.lock().await, &device);
+    }
+    result.map_err(|err| err.to_string())
+}
+
+/// Accepts the files in `selected_file_ids` of the send request shown to the user, declines the
+/// request if there are none
+#[tauri::command]
+async fn respond_to_send_request(
+    prompts: tauri::State<'_, Arc<Prompts>>,
+    request_id: String,
+    selected_file_ids: Option<Vec<String>>,
+) -> Result<(), String> {
+    let reply = prompts
+        .pending_requests
+        .lock()
+        .await
+        .remove(&request_id)
+        .ok_or_else(|| format!("No pending send request {}", request_id))?;
+
+    let client_message = match selected_file_ids {
+        Some(file_ids) if !file_ids.is_empty() => ClientMessage::Allow(file_ids),
+        _ => ClientMessage::Decline,
+    };
+    // the server stops waiting after a while, or as soon as the sender gives up
+    reply
+        .send(client_message)
+        .map_err(|_| format!("Send request {} is no longer pending", request_id))
+}
+
+#[tauri::command]
+async fn resolve_file_conflict(
+    prompts: tauri::State<'_, Arc<Prompts>>,
+    conflict_id: String,
+    conflict_policy: ConflictPolicy,
+) -> Result<(), String> {
+    let reply = prompts
+        .pending_conflicts
+        .lock()
+        .await
+        .remove(&conflict_id)
+        .ok_or_else(|| format!("No pending file conflict {}", conflict_id))?;
+    reply
+        .send(conflict_policy)
+        .map_err(|_| "The file conflict is no longer pending".to_string())
+}
+
+/// Passes the questions of the server and the progress of every session on to the frontend
+async fn forward_server_msgs(
+    app: AppHandle,
+    mut server_rx: Receiver<ServerMessage>,
+    prompts: Arc<Prompts>,
+) {
+    let mut trackers: HashMap<String, ProgressTracker> = HashMap::new(); // keyed by session id
+    while let Some(server_message) = server_rx.recv().await {
+        match server_message {
+            ServerMessage::SendRequest((send_request, space_shortage, reply)) => {
+                let request_id = Uuid::new_v4().to_string();
+                prompts
+                    .pending_requests
+                    .lock()
+                    .await
+                    .insert(request_id.clone(), reply);
+
+                let mut files = send_request.files.into_values().collect::<Vec<_>>();
+                files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+                let total_size = files
+                    .iter()
+                    .map(|file_info| file_info.size as u64)
+                    .sum::<u64>();
+                let mut folders: BTreeMap<String, FileFolder> = BTreeMap::new();
+                for file_info in &files {
+                    if let Some(name) = top_level_folder(&file_info.file_name) {
+                        let folder = folders.entry(name.clone()).or_insert(FileFolder {
+                            name,
+                            file_ids: Vec::new(),
+                            size: 0,
+                        });
+                        folder.file_ids.push(file_info.id.clone());
+                        folder.size += file_info.size as u64;
+                    }
+                }
+                let _ = app.emit(
+                    SEND_REQUEST_EVENT,
+                    SendRequestEvent {
+                        request_id,
+                        sender: send_request.device_info,
+                        files,
+                        folders: folders.into_values().collect(),
+                        total_size,
+                        space_shortage,
+                    },
+                );
+            }
+            ServerMessage::FileConflict((request_id, file_id, path, reply)) => {
+                // concurrent requests can use the same file ids
+                let conflict_id = format!("{}/{}", request_id, file_id);
+                prompts
+                    .pending_conflicts
+                    .lock()
+                    .await
+                    .insert(conflict_id.clone(), reply);
+                let _ = app.emit(
+                    FILE_CONFLICT_EVENT,
+                    FileConflictEvent {
+                        conflict_id,
+                        file_id,
+                        path,
+                    },
+                );
+            }
+            ServerMessage::TextMessage((sender, text)) => {
+                let _ = app.emit(TEXT_MESSAGE_EVENT, TextMessageEvent { sender, text });
+            }
+            ServerMessage::SessionStarted((session_id, files)) => {
+                trackers.insert(
+                    session_id.clone(),
+                    ProgressTracker::new(session_id.clone(), &files),
+                );
+                let mut files = files.into_values().collect::<Vec<_>>();
+                files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+                let _ = app.emit(
+                    SESSION_EVENT,
+                    SessionEvent {
+                        session_id,
+                        kind: SessionEventKind::Started,
+                        files,
+                    },
+                );
+            }
+            ServerMessage::FileStarted((session_id, file_id, offset)) => {
+                if let Some(event) = trackers
+                    .get_mut(&session_id)
+                    .and_then(|tracker| tracker.started(&file_id, offset))
+                {
+                    let _ = app.emit(FILE_PROGRESS_EVENT, event);
+                }
+            }
+            ServerMessage::FileInterrupted((session_id, file_id)) => {
+                if let Some(event) = trackers
+                    .get_mut(&session_id)
+                    .and_then(|tracker| tracker.interrupted(&file_id))
+                {
+                    let _ = app.emit(FILE_PROGRESS_EVENT, event);
+                }
+            }
+            ServerMessage::SendFileRequest((session_id, file_id, len)) => {
+                if let Some(event) = trackers
+                    .get_mut(&session_id)
+                    .and_then(|tracker| tracker.received(&file_id, len))
+                {
+                    let _ = app.emit(FILE_PROGRESS_EVENT, event);
+                }
+            }
+            ServerMessage::FileFinished((session_id, file_id, error)) => {
+                if let Some(event) = trackers
+                    .get_mut(&session_id)
+                    .and_then(|tracker| tracker.finished(&file_id, error))
+                {
+                    let _ = app.emit(FILE_PROGRESS_EVENT, event);
+                }
+            }
+            ServerMessage::SessionFinished((session_id, status)) => {
+                let kind = match status {
+                    ReceiveStatus::Finished => SessionEventKind::Finished,
+                    _ => SessionEventKind::FinishedWithErrors,
+                };
+                emit_session_end(&app, trackers.remove(&session_id), kind);
+            }
+            ServerMessage::CancelSession(session_id) => {
+                let tracker = trackers.remove(&session_id);
+                emit_session_end(&app, tracker, SessionEventKind::Cancelled);
+            }
+            ServerMessage::PromptExpired => {
+                let expired = prompts.remove_expired().await;
+                if !expired.is_empty() {
+                    let _ = app.emit(PROMPT_EXPIRED_EVENT, expired);
+                }
+            }
+            ServerMessage::SessionTimedOut(session_id) => {
+                let tracker = trackers.remove(&session_id);
+                emit_session_end(&app, tracker, SessionEventKind::TimedOut);
+            }
+            ServerMessage::UntrustedDevice((device, pinned, blocked)) => {
+                let _ = app.emit(
+                    UNTRUSTED_DEVICE_EVENT,
+                    UntrustedDeviceEvent {
+                        device,
+                        pinned,
+                        blocked,
+                    },
+                );
+            }
+        }
+    }
+}
+
+fn emit_session_end(app: &AppHandle, tracker: Option<ProgressTracker>, kind: SessionEventKind) {
+    if let Some(tracker) = tracker {
+        let _ = app.emit(
+            SESSION_EVENT,
+            SessionEvent {
+                session_id: tracker.session_id().to_string(),
+                kind,
+                files: vec![],
+            },
+        );
+    }
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+#[tokio::main]
+pub async fn run() {
+    init_tracing();
+    let (app_state, server_rx) = app_state(ALIAS, Settings::load());
+    spawn_discovery(&app_state);
+    spawn_server(&app_state);
+    let prompts = Arc::new(Prompts::default());
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_shell::init())
+        .invoke_handler(tauri::generate_handler![
+            get_nearby_devices,
+            get_settings,
+            update_settings,
+            set_favorite_device,
+            get_fingerprint,
+            regenerate_identity,
+            get_trusted_devices,
+            forget_trusted_device,
+            get_history,
+            open_history_folder,
+            send_text,
+            respond_to_send_request,
+            resolve_file_conflict
+        ])
+        .setup(|app| {
+            tokio::spawn(forward_server_msgs(
+                app.handle().clone(),
+                server_rx,
+                prompts.clone(),
+            ));
+            app.manage(app_state);
+            app.manage(prompts);
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
@@ -0,0 +1,5 @@
+pub mod core;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod models;
+pub mod terminal;
@@ -1,12 +1,22 @@
-use std::{collections::HashMap, net::Ipv4Addr, sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
+use axum_server::tls_rustls::RustlsConfig;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{
     mpsc::{UnboundedReceiver, UnboundedSender},
-    Mutex,
+    oneshot, watch, Mutex, Semaphore,
 };
 
 use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -16,8 +26,14 @@ pub struct DeviceInfo {
     pub device_model: Option<String>,
     #[serde(skip)]
     pub ip: String,
-    #[serde(skip)]
-    pub port: u16,
+    #[serde(default)]
+    pub port: u16, // port of the http(s) server of the device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>, // protocol version, missing for v1 devices
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>, // `http` or `https`, missing for v1 devices which use https
+    #[serde(default)]
+    pub fingerprint: String, // missing in v1 send requests
     pub ip_ending: Option<String>,
 }
 
@@ -35,6 +51,9 @@ impl Default for DeviceInfo {
             device_model: None,
             ip: "".into(),
             port: 0,
+            version: None,
+            protocol: None,
+            fingerprint: "".into(),
             ip_ending: None,
         }
     }
@@ -46,14 +65,12 @@ pub struct DeviceResponse {
     #[serde(flatten)]
     pub device_info: DeviceInfo,
     pub announcement: bool,
-    pub fingerprint: String,
 }
 
 impl From<DeviceInfo> for DeviceResponse {
     fn from(device: DeviceInfo) -> Self {
         Self {
             device_info: device,
-            fingerprint: "".into(),
             announcement: false,
         }
     }
@@ -62,7 +79,7 @@ impl From<DeviceInfo> for DeviceResponse {
 impl PartialEq for DeviceResponse {
     // https://www.reddit.com/r/rust/comments/t8d6wb/comment/hznabrt
     fn eq(&self, other: &Self) -> bool {
-        self.fingerprint == other.fingerprint
+        self.device_info.fingerprint == other.device_info.fingerprint
     }
 }
 
@@ -79,9 +96,10 @@ pub struct LocalSendDevice {
 pub type ReceiveState = Arc<Mutex<AppState>>;
 pub type Sender<T> = UnboundedSender<T>;
 pub type Receiver<T> = UnboundedReceiver<T>;
+pub type Reply<T> = oneshot::Sender<T>; // answers a single question of the server
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", from = "String")]
 pub enum FileType {
     Image,
     Video,
@@ -90,13 +108,99 @@ pub enum FileType {
     Other,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+impl From<String> for FileType {
+    // v1 peers send one of the variant names, v2 peers send the mime type of the file
+    fn from(file_type: String) -> Self {
+        match file_type.as_str() {
+            "image" => Self::Image,
+            "video" => Self::Video,
+            "pdf" | "application/pdf" => Self::Pdf,
+            "text" => Self::Text,
+            mime if mime.starts_with("image/") => Self::Image,
+            mime if mime.starts_with("video/") => Self::Video,
+            mime if mime.starts_with("text/") => Self::Text,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl FileType {
+    /// Guesses the type of a file that is about to be sent from its extension
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp" | "heic" | "heif" | "svg" | "tif"
+            | "tiff" => Self::Image,
+            "mp4" | "mov" | "mkv" | "webm" | "avi" | "m4v" | "3gp" => Self::Video,
+            "pdf" => Self::Pdf,
+            "txt" | "md" | "csv" | "log" | "json" => Self::Text,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum ReceiveStatus {
-    // TODO: add status for cancelled
     Waiting,            // waiting for sender to send the files
     Receiving,          // in an ongoing session, receiving files
     Finished,           // all files received (end of session)
     FinishedWithErrors, // finished but some files could not be received (end of session)
+    Cancelled,          // the session ended before the file was received (end of session)
+}
+
+/// What to do when a received file would replace an existing file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Rename, // save as `name (1).ext`
+    Overwrite,
+    Skip, // don't receive the file at all
+    Ask,  // let the user pick one of the above for each conflicting file
+}
+
+/// Which send requests are accepted without asking the user
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceivePolicy {
+    #[default]
+    Ask,
+    Favorites, // accept requests of favorite devices, ask about the others
+    Everyone,
+}
+
+/// A device whose requests are accepted without asking if the receive policy says so
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FavoriteDevice {
+    pub fingerprint: String,
+    pub alias: String, // only to show the device to the user
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub download_directory: PathBuf,
+    pub session_subfolders: bool, // save into `<alias>/<date>/` inside the download directory
+    pub route_by_file_type: bool, // save images to Pictures and videos to Videos
+    pub conflict_policy: ConflictPolicy,
+    pub prompt_timeout: u64, // seconds to wait for an answer before declining a request
+    pub idle_timeout: u64,   // seconds a session may go without an upload before it is dropped
+    pub stall_timeout: u64,  // seconds an upload may go without data before it is dropped
+    pub max_sessions: usize, // sessions that can be open or waiting for the user at once
+    pub max_parallel_uploads: usize, // uploads of a session that are received at once
+    pub receive_policy: ReceivePolicy,
+    pub favorite_devices: Vec<FavoriteDevice>,
+    // requests that go over the limits below are asked about, whatever the receive policy
+    pub auto_accept_max_size: Option<u64>, // bytes of all files of a request
+    pub auto_accept_file_types: Vec<FileType>, // empty allows every type
+    pub auto_accept_extensions: Vec<String>, // lowercase without the dot, empty allows every one
+    pub receive_pin: Option<String>,       // senders have to know it if it is set and not empty
+    pub block_untrusted_devices: bool, // keep out devices that don't match their pinned fingerprint
 }
 
 #[derive(Clone, Debug)]
@@ -105,11 +209,179 @@ pub enum ClientMessage {
     Decline,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ServerMessage {
-    SendRequest(SendRequest),
-    SendFileRequest((String, usize)),
-    CancelSession,
+    SendRequest((SendRequest, Option<SpaceShortage>, Reply<ClientMessage>)), // a destination directory the files don't fit in
+    FileConflict((String, String, PathBuf, Reply<ConflictPolicy>)),          // request and file id
+    SessionStarted((String, HashMap<String, FileInfo>)), // session id and the accepted files
+    TextMessage((DeviceInfo, String)), // a request that only carried text, nothing is saved
+    // the messages below start with the id of the session they belong to
+    FileStarted((String, String, u64)), // the offset the upload starts at, more than 0 if resumed
+    SendFileRequest((String, String, usize)),
+    FileInterrupted((String, String)), // the upload stopped but can still be resumed
+    FileFinished((String, String, Option<String>)), // the error if the file could not be received
+    SessionFinished((String, ReceiveStatus)), // every file of the session was received or failed
+    CancelSession(String),
+    SessionTimedOut(String), // the sender went quiet and the session was dropped
+    PromptExpired,           // a question is no longer waiting, it timed out or the sender gave up
+    // a device that doesn't match the fingerprint pinned for its alias, and whether it was
+    // kept out
+    UntrustedDevice((DeviceInfo, TrustedDevice, bool)),
+}
+
+/// Send request as it is shown to the user of the desktop app
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendRequestEvent {
+    pub request_id: String,
+    pub sender: DeviceInfo,
+    pub files: Vec<FileInfo>,
+    pub folders: Vec<FileFolder>, // files sent as `folder/sub/file` are accepted as a whole
+    pub total_size: u64,
+    pub space_shortage: Option<SpaceShortage>,
+}
+
+/// The files of a send request that are in the same top-level folder
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileFolder {
+    pub name: String,
+    pub file_ids: Vec<String>,
+    pub size: u64,
+}
+
+/// A destination directory that doesn't have enough space left for the files that go there
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpaceShortage {
+    pub directory: String,
+    pub needed: u64,
+    pub free: u64,
+}
+
+/// Device that doesn't match the fingerprint pinned for its alias, as it is shown to the
+/// user of the desktop app
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UntrustedDeviceEvent {
+    pub device: DeviceInfo,
+    pub pinned: TrustedDevice,
+    pub blocked: bool,
+}
+
+/// Text message as it is shown to the user of the desktop app
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextMessageEvent {
+    pub sender: DeviceInfo,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileConflictEvent {
+    pub conflict_id: String, // the request id and the file id, senders choose their file ids
+    pub file_id: String,
+    pub path: PathBuf, // where the file already exists
+}
+
+/// Progress of a file that is being received, sent to the desktop app a few times per second
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileProgressEvent {
+    pub session_id: String,
+    pub file_id: String,
+    pub file_name: String,
+    pub bytes_done: u64,
+    pub total_bytes: u64,
+    pub speed: u64,       // bytes per second
+    pub eta: Option<u64>, // seconds, `None` until there is a speed to go by
+    pub status: ReceiveStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionEventKind {
+    Started,
+    Finished,
+    FinishedWithErrors,
+    Cancelled,
+    TimedOut,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionEvent {
+    pub session_id: String,
+    pub kind: SessionEventKind,
+    pub files: Vec<FileInfo>, // the accepted files, only set when the session started
+}
+
+/// Progress of an outgoing session, reported by `SendClient`
+#[derive(Clone, Debug)]
+pub enum SenderMessage {
+    Waiting,                 // waiting for the receiver to accept the request
+    Accepted(Vec<FileInfo>), // the files the receiver wants
+    Progress((String, u64)), // bytes of a file that were sent so far
+    FileFinished(String),
+    FileFailed((String, String)), // file id and why it could not be sent
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    Declined, // the receiver declined the request
+    Busy,     // the receiver is in another session
+    PinRequired,
+    WrongPin,
+    TooManyAttempts, // the receiver blocks this device for a while after too many wrong PINs
+    FingerprintMismatch, // the certificate of the receiver isn't the one it announced
+    Cancelled,
+    NoFiles,
+    FilesFailed(Vec<(String, String)>), // file name and error of every file that wasn't sent
+    Status((u16, String)),              // unexpected response status and body
+    Request(reqwest::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Declined => write!(f, "The receiver declined the request"),
+            Self::Busy => write!(f, "The receiver is busy with another session"),
+            Self::PinRequired => write!(f, "The receiver requires a PIN"),
+            Self::WrongPin => write!(f, "The PIN is wrong"),
+            Self::TooManyAttempts => write!(f, "Too many wrong PINs, try again later"),
+            Self::FingerprintMismatch => write!(
+                f,
+                "The certificate of the receiver doesn't match its fingerprint"
+            ),
+            Self::Cancelled => write!(f, "The session was cancelled"),
+            Self::NoFiles => write!(f, "There are no files to send"),
+            Self::FilesFailed(failed) => write!(f, "{} file(s) could not be sent", failed.len()),
+            Self::Status((status, body)) => write!(f, "Unexpected response {}: {}", status, body),
+            Self::Request(err) => write!(f, "Request failed: {}", err),
+            Self::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+impl From<reqwest::Error> for SendError {
+    fn from(err: reqwest::Error) -> Self {
+        if crate::core::trust::is_fingerprint_mismatch(&err) {
+            return Self::FingerprintMismatch;
+        }
+        Self::Request(err)
+    }
+}
+
+impl From<io::Error> for SendError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -119,20 +391,23 @@ pub struct FileInfo {
     pub size: usize, // bytes
     pub file_name: String,
     pub file_type: FileType,
-    // pub token: String,   // TODO: use this to verify while receiving the file
-    // preview_data: type? // nullable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>, // hex encoded, optional in v2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>, // the content of text files or a thumbnail as a data url
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct RegisterRequest {
-    alias: String,
-    version: String,
-    deviceModel: String,
-    deviceType: String,
-    fingerprint: String,
-    port: u16,
-    protocol: String,
-    download: Option<bool>, // if the download API (5.2 and 5.3) is active (optional, default: false)
+    pub alias: String,
+    pub version: String,
+    pub device_model: Option<String>,
+    pub device_type: String,
+    pub fingerprint: String,
+    pub port: u16,
+    pub protocol: String,
+    pub download: Option<bool>, // if the download API (5.2 and 5.3) is active (optional, default: false)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -142,39 +417,192 @@ pub struct SendRequest {
     pub files: HashMap<String, FileInfo>,
 }
 
+impl SendRequest {
+    /// The text of a request that only carries text messages, nothing has to be uploaded for it
+    pub fn text_message(&self) -> Option<String> {
+        if self.files.is_empty() {
+            return None;
+        }
+        let mut texts = self
+            .files
+            .values()
+            .map(|file_info| match file_info.file_type {
+                FileType::Text => file_info.preview.as_deref(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        texts.sort();
+        Some(texts.join("\n"))
+    }
+}
+
+/// Body of an outgoing `/prepare-upload` or `/send-request`, v1 peers ignore the fields they don't
+/// know about
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PrepareUploadRequest {
+    pub info: RegisterRequest,
+    pub files: HashMap<String, FileInfo>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SendInfo {
     pub file_id: String,
-    pub token: String,
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelInfo {
+    pub session_id: Option<String>, // v1 senders cancel without one
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PinInfo {
+    pub pin: Option<String>, // only needed if the receiver asks for one with a 401
+}
+
+/// The key pair and self signed certificate this device serves https with, kept across launches
+/// so that other devices can recognize it
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub certificate_pem: String,
+    pub private_key_pem: String,
+    pub fingerprint: String, // hex encoded sha256 of the certificate
+}
+
+/// A device whose fingerprint was pinned the first time it was seen
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedDevice {
+    pub fingerprint: String,
+    pub alias: String, // the alias and ip it was last seen with
+    pub ip: String,
+    pub first_seen: String, // rfc 3339
+}
+
+/// Wrong PINs a sender entered recently, it is blocked after too many
+pub struct PinAttempts {
+    pub failures: u32,
+    pub last_failure: Instant,
+}
+
+/// Text messages a sender sent recently, more are turned away until the window is over
+pub struct TextMessages {
+    pub count: u32,
+    pub window_start: Instant,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadInfo {
+    pub session_id: String,
+    pub file_id: String,
+    pub token: Option<String>,
+    pub offset: Option<u64>, // localsend-rs extension, resumes an interrupted upload
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadOffsetResponse {
+    pub offset: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrepareUploadResponse {
+    pub session_id: String,
+    pub files: HashMap<String, String>,
 }
 
 #[derive(Clone)]
 pub struct ReceiveSession {
+    pub session_id: String,
     pub sender: DeviceInfo,
     pub files: HashMap<String, FileInfo>,
+    pub file_tokens: HashMap<String, String>,
+    pub file_paths: HashMap<String, PathBuf>, // final target path of each file
     pub file_status: HashMap<String, ReceiveStatus>,
+    pub file_errors: HashMap<String, String>, // why a file finished with errors
+    pub interrupted_at: HashMap<String, Instant>, // uploads that can still be resumed
     pub destination_directory: String,
+    pub root_folder: Option<PathBuf>, // set if a folder was sent, where it was saved
     pub start_time: Instant,
+    pub last_activity: Instant, // when an upload last started or ended
     pub status: ReceiveStatus,
+    pub cancel_token: CancellationToken, // cancelled when the session is cancelled by the sender
+    pub upload_slots: Arc<Semaphore>,    // limits the uploads that are received at once
 }
 
 impl ReceiveSession {
-    pub fn new(sender: DeviceInfo, destination_directory: String) -> Self {
+    pub fn new(
+        sender: DeviceInfo,
+        destination_directory: String,
+        max_parallel_uploads: usize,
+    ) -> Self {
         Self {
+            session_id: Uuid::new_v4().to_string(),
             sender,
             destination_directory,
+            root_folder: None,
             files: HashMap::new(),
+            file_tokens: HashMap::new(),
+            file_paths: HashMap::new(),
             file_status: HashMap::new(),
+            file_errors: HashMap::new(),
+            interrupted_at: HashMap::new(),
             start_time: Instant::now(),
+            last_activity: Instant::now(),
             status: ReceiveStatus::Waiting,
+            cancel_token: CancellationToken::new(),
+            upload_slots: Arc::new(Semaphore::new(max_parallel_uploads.max(1))),
         }
     }
 }
 
+/// A received file as it is kept in the history
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryFile {
+    pub file_name: String,
+    pub path: PathBuf,
+    pub size: usize,
+    pub status: ReceiveStatus,
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>, // only filled in when the history is shown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview_path: Option<PathBuf>, // previews are kept out of the history file
+}
+
+/// A receive session that ended, persisted so that received files can be found again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    pub session_id: String,
+    pub sender_alias: String,
+    pub received_at: String, // rfc 3339
+    pub status: ReceiveStatus,
+    pub folder: Option<PathBuf>, // root folder of a folder transfer, `None` for loose files
+    pub files: Vec<HistoryFile>,
+}
+
+/// A send request that is waiting for the user to answer
+pub struct PendingRequest {
+    pub sender_ip: String,
+    pub cancel_token: CancellationToken, // cancelled when the sender cancels the request
+}
+
 pub struct AppState {
-    pub(crate) device: LocalSendDevice,
+    pub device: LocalSendDevice,
+    pub(crate) announced_device: watch::Sender<DeviceResponse>, // what discovery announces
     pub(crate) server_tx: Sender<ServerMessage>,
-    pub(crate) client_rx: Receiver<ClientMessage>,
-    pub(crate) receive_session: Option<ReceiveSession>,
+    // keyed by request id, also removed from when the request handler is dropped
+    pub(crate) pending_requests: Arc<std::sync::Mutex<HashMap<String, PendingRequest>>>,
+    pub(crate) receive_sessions: HashMap<String, ReceiveSession>, // keyed by session id
+    pub(crate) pin_attempts: HashMap<IpAddr, PinAttempts>,        // keyed by sender ip
+    pub(crate) text_messages: HashMap<IpAddr, TextMessages>,      // keyed by sender ip
+    pub(crate) identity: Identity,
+    pub(crate) tls_config: Option<RustlsConfig>, // set once the server is running
+    pub trusted_devices: Vec<TrustedDevice>,
+    pub settings: Settings,
 }
@@ -0,0 +1,328 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use console::style;
+use dialoguer::{theme::ColorfulTheme, Confirm, MultiSelect, Select};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use tracing::info;
+
+use crate::{
+    core::sanitize::top_level_folder,
+    models::{
+        ClientMessage, ConflictPolicy, DeviceInfo, FileInfo, Reply, SendRequest, ServerMessage,
+        SpaceShortage,
+    },
+};
+
+/// Files of a session that is being received and their progress bars
+struct State {
+    files: HashMap<String, FileInfo>,
+    progress_map: HashMap<String, ProgressBar>,
+}
+
+/// Answers the messages of the server in the terminal and shows the progress of received files
+pub struct Terminal {
+    auto_accept: bool, // never ask, requests that the receive policy didn't accept are declined
+    multi_progress: MultiProgress,
+    sessions: HashMap<String, State>, // keyed by session id
+}
+
+impl Terminal {
+    pub fn new(auto_accept: bool) -> Self {
+        Self {
+            auto_accept,
+            multi_progress: MultiProgress::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn handle(&mut self, server_message: ServerMessage) {
+        match server_message {
+            ServerMessage::SendRequest((send_request, space_shortage, reply)) => {
+                self.handle_send_request(send_request, space_shortage, reply)
+            }
+            ServerMessage::FileConflict((_, _, path, reply)) => {
+                // the session only starts once every conflict is resolved, so there is no name yet
+                let file_name = path
+                    .file_name()
+                    .map(|file_name| file_name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                if self.auto_accept {
+                    println!(
+                        "{} already exists at {}, keeping both",
+                        style(file_name).bold(),
+                        path.display()
+                    );
+                    let _ = reply.send(ConflictPolicy::Rename);
+                    return;
+                }
+
+                let conflict_policies = [
+                    ConflictPolicy::Rename,
+                    ConflictPolicy::Overwrite,
+                    ConflictPolicy::Skip,
+                ];
+                let selection = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt(format!(
+                        "{} already exists at {}",
+                        style(file_name).bold(),
+                        path.display()
+                    ))
+                    .items(&["Keep both", "Overwrite", "Skip"])
+                    .default(0)
+                    .interact()
+                    .unwrap();
+                if reply.send(conflict_policies[selection]).is_err() {
+                    println!("{}", style("The sender did not wait for an answer").red());
+                }
+            }
+            ServerMessage::SessionStarted((session_id, files)) => {
+                let progress_map = files
+                    .iter()
+                    .map(|(file_id, file_info)| {
+                        (
+                            file_id.clone(),
+                            progress_bar(&self.multi_progress, file_info),
+                        )
+                    })
+                    .collect::<HashMap<String, ProgressBar>>();
+                self.sessions.insert(
+                    session_id,
+                    State {
+                        files,
+                        progress_map,
+                    },
+                );
+            }
+            ServerMessage::TextMessage((sender, text)) => self.handle_text_message(sender, text),
+            ServerMessage::FileStarted((session_id, file_id, offset)) => {
+                if let Some(pb) = self
+                    .sessions
+                    .get(&session_id)
+                    .and_then(|state| state.progress_map.get(&file_id))
+                {
+                    pb.reset();
+                    pb.set_position(offset);
+                }
+            }
+            ServerMessage::FileInterrupted((session_id, file_id)) => {
+                if let Some(state) = self.sessions.get(&session_id) {
+                    let line = format!(
+                        "{} was interrupted, waiting for the sender to resume it",
+                        state.files[&file_id].file_name
+                    );
+                    self.multi_progress.println(line).unwrap();
+                }
+            }
+            ServerMessage::SendFileRequest((session_id, file_id, size)) => {
+                match self.sessions.get(&session_id) {
+                    Some(state) => {
+                        if let Some(pb) = state.progress_map.get(&file_id) {
+                            pb.inc(size as u64);
+                        }
+                    }
+                    None => {
+                        info!("no state for session {}. this shouldn't be happening as this block is unreachable.", session_id)
+                    }
+                }
+            }
+            ServerMessage::FileFinished((session_id, file_id, error)) => {
+                if let Some(state) = self.sessions.get(&session_id) {
+                    if let Some(pb) = state.progress_map.get(&file_id) {
+                        pb.finish_and_clear();
+                    }
+                    let file_name = &state.files[&file_id].file_name;
+                    let line = match error {
+                        None => format!("Received {}", file_name),
+                        Some(error) => format!("{} finished with error: {}", file_name, error),
+                    };
+                    self.multi_progress.println(line).unwrap();
+                }
+            }
+            ServerMessage::SessionFinished((session_id, _)) => {
+                self.sessions.remove(&session_id);
+            }
+            // prompts block until they are answered, a late answer is reported when it is sent
+            ServerMessage::PromptExpired => {}
+            // it is logged when it is detected, which also covers `scan` and `send`
+            ServerMessage::UntrustedDevice(_) => {}
+            ServerMessage::SessionTimedOut(session_id) => {
+                println!(
+                    "{}",
+                    style("The sender stopped responding, the session was dropped").red()
+                );
+                self.handle(ServerMessage::CancelSession(session_id));
+            }
+            ServerMessage::CancelSession(session_id) => match self.sessions.remove(&session_id) {
+                Some(state) => {
+                    for (file_id, pb) in &state.progress_map {
+                        if !pb.is_finished() {
+                            pb.finish_and_clear();
+                            self.multi_progress
+                                .println(format!(
+                                    "{} finished with error",
+                                    state.files[file_id.as_str()].file_name
+                                ))
+                                .unwrap();
+                        }
+                    }
+                }
+                None => {
+                    info!("no state for session {}. this shouldn't be happening as this block is unreachable.", session_id)
+                }
+            },
+        }
+    }
+
+    fn handle_text_message(&self, sender: DeviceInfo, text: String) {
+        println!(
+            "{} sent you a message:\n\n{}\n",
+            style(&sender.alias).bold().magenta(),
+            text
+        );
+        if self.auto_accept {
+            return;
+        }
+        let copy = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Copy the message to the clipboard?")
+            .default(false)
+            .interact()
+            .unwrap();
+        if copy {
+            // asks the terminal to copy it, which also works over ssh
+            print!("\x1b]52;c;{}\x07", STANDARD.encode(&text));
+        }
+    }
+
+    fn handle_send_request(
+        &mut self,
+        send_request: SendRequest,
+        space_shortage: Option<SpaceShortage>,
+        reply: Reply<ClientMessage>,
+    ) {
+        // the sender may have cancelled while another request was being answered
+        if reply.is_closed() {
+            println!(
+                "{} cancelled the request",
+                style(&send_request.device_info.alias).bold().magenta()
+            );
+            return;
+        }
+        println!(
+            "{} wants to send you the following files:\n",
+            style(&send_request.device_info.alias).bold().magenta()
+        );
+
+        let total_size = send_request
+            .files
+            .values()
+            .map(|file_info| file_info.size as u64)
+            .sum::<u64>();
+        println!("{} in total\n", HumanBytes(total_size));
+        if let Some(space_shortage) = space_shortage {
+            println!(
+                "{} {} are needed in {} but only {} are free, {} short\n",
+                style("Not enough space:").bold().red(),
+                HumanBytes(space_shortage.needed),
+                space_shortage.directory,
+                HumanBytes(space_shortage.free),
+                HumanBytes(space_shortage.needed - space_shortage.free)
+            );
+        }
+
+        // files of a folder are sent as `folder/sub/file`, they are accepted as a whole
+        let groups = group_by_folder(&send_request.files);
+        if self.auto_accept {
+            for (label, _) in &groups {
+                println!("  {}", label);
+            }
+            // requests that the receive policy accepts never get here
+            println!(
+                "{}",
+                style("Declined, the request is over the auto accept limits").red()
+            );
+            let _ = reply.send(ClientMessage::Decline);
+            return;
+        }
+        let selections = MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select the files you want to receive")
+            .items(
+                &groups
+                    .iter()
+                    .map(|(label, _)| label.as_str())
+                    .collect::<Vec<&str>>(),
+            )
+            .defaults(vec![true; groups.len()].as_slice())
+            .interact()
+            .unwrap();
+        let selected_file_ids = selections
+            .into_iter()
+            .flat_map(|idx| groups[idx].1.clone())
+            .collect::<Vec<_>>();
+
+        if selected_file_ids.is_empty() {
+            let _ = reply.send(ClientMessage::Decline);
+            return;
+        }
+        // the server stops waiting after a while, or as soon as the sender gives up
+        // the progress bars are set up once the session starts
+        if reply.send(ClientMessage::Allow(selected_file_ids)).is_err() {
+            println!(
+                "{}",
+                style("The request was cancelled or is no longer pending").red()
+            );
+        }
+    }
+}
+
+/// Adds a bar for the transfer of `file_info` to `multi_progress`
+pub fn progress_bar(multi_progress: &MultiProgress, file_info: &FileInfo) -> ProgressBar {
+    let pb = multi_progress.add(ProgressBar::new(file_info.size as u64));
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{spinner:.green} [{msg}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+        )
+        .unwrap()
+        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+            write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
+        })
+        .progress_chars("#>-"),
+    );
+    pb.set_message(file_info.file_name.clone());
+    pb
+}
+
+/// Groups the files of a send request by their top-level folder. Returns a label and the file ids
+/// of every group, files that are not in a folder are a group of their own.
+fn group_by_folder(files: &HashMap<String, FileInfo>) -> Vec<(String, Vec<String>)> {
+    let mut folders: BTreeMap<String, (Vec<String>, u64)> = BTreeMap::new();
+    let mut groups = Vec::new();
+    for (file_id, file_info) in files {
+        match top_level_folder(&file_info.file_name) {
+            Some(folder) => {
+                let (file_ids, size) = folders.entry(folder).or_default();
+                file_ids.push(file_id.clone());
+                *size += file_info.size as u64;
+            }
+            None => groups.push((file_info.file_name.clone(), vec![file_id.clone()])),
+        }
+    }
+    groups.sort();
+
+    folders
+        .into_iter()
+        .map(|(folder, (file_ids, size))| {
+            let label = format!(
+                "{}/ ({} files, {})",
+                folder,
+                file_ids.len(),
+                HumanBytes(size)
+            );
+            (label, file_ids)
+        })
+        .chain(groups)
+        .collect()
+}
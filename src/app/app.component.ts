@@ -3,12 +3,13 @@ import { CommonModule } from '@angular/common';
 import { RouterOutlet } from '@angular/router';
 import { invoke } from "@tauri-apps/api/core";
 import { SidePanelComponent } from './modules/side-panel/side-panel.component';
+import { SendRequestComponent } from './modules/send-request/send-request.component';
 import { LocalSendService } from './shared/services/localsend.service';
 
 @Component({
   selector: 'app-root',
   standalone: true,
-  imports: [CommonModule, RouterOutlet, SidePanelComponent],
+  imports: [CommonModule, RouterOutlet, SidePanelComponent, SendRequestComponent],
   providers: [LocalSendService],
   templateUrl: './app.component.html',
   styleUrl: './app.component.css'
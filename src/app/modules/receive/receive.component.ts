@@ -1,5 +1,10 @@
-import { AfterViewInit, Component } from '@angular/core';
+import { AfterViewInit, Component, OnDestroy, OnInit } from '@angular/core';
+import { invoke } from '@tauri-apps/api/core';
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
 import { LocalSendService } from '../../shared/services/localsend.service';
+import { HistoryEntryModel } from '../../shared/models/history.model';
+import { FileProgressModel, ReceivingSessionModel, SessionEventModel } from '../../shared/models/progress.model';
+import { formatBytes } from '../../shared/utils';
 
 @Component({
   selector: 'app-receive',
@@ -8,10 +13,73 @@ import { LocalSendService } from '../../shared/services/localsend.service';
   templateUrl: './receive.component.html',
   styleUrl: './receive.component.css'
 })
-export class ReceiveComponent implements AfterViewInit {
+export class ReceiveComponent implements OnInit, AfterViewInit, OnDestroy {
+  history: HistoryEntryModel[] = []
+  // sessions that are being received, ended ones are kept until the next session starts
+  sessions: ReceivingSessionModel[] = [];
+  formatBytes = formatBytes;
+  private unlisten: UnlistenFn[] = [];
+
   constructor(public localSendService: LocalSendService) {
   }
 
+  async ngOnInit() {
+    this.unlisten.push(await listen<SessionEventModel>('receive-session', (event) => {
+      const session = event.payload;
+      if (session.kind === 'started') {
+        this.sessions = this.sessions.filter((receiving) => receiving.state === 'started');
+        this.sessions.push({
+          sessionId: session.sessionId,
+          state: session.kind,
+          files: session.files.map((file) => ({
+            sessionId: session.sessionId,
+            fileId: file.id,
+            fileName: file.fileName,
+            bytesDone: 0,
+            totalBytes: file.size,
+            speed: 0,
+            eta: null,
+            status: 'waiting',
+            error: null
+          }))
+        });
+        return;
+      }
+      const receiving = this.sessions.find((receiving) => receiving.sessionId === session.sessionId);
+      if (receiving) {
+        receiving.state = session.kind;
+        this.get_history();
+      }
+    }));
+    this.unlisten.push(await listen<FileProgressModel>('receive-progress', (event) => {
+      const receiving = this.sessions.find((receiving) => receiving.sessionId === event.payload.sessionId);
+      const index = receiving?.files.findIndex((file) => file.fileId === event.payload.fileId) ?? -1;
+      if (receiving && index !== -1) {
+        receiving.files[index] = event.payload;
+      }
+    }));
+  }
+
   ngAfterViewInit(): void {
+    this.get_history();
+  }
+
+  ngOnDestroy(): void {
+    this.unlisten.forEach((unlisten) => unlisten());
+  }
+
+  get_history() {
+    invoke('get_history')
+      .then((history) => this.history = history as HistoryEntryModel[])
+      .catch((error) => console.error(error));
+  }
+
+  open_folder(entry: HistoryEntryModel) {
+    invoke('open_history_folder', { sessionId: entry.sessionId })
+      .catch((error) => console.error(error));
+  }
+
+  percent(file: FileProgressModel): number {
+    return file.totalBytes > 0 ? Math.floor(file.bytesDone * 100 / file.totalBytes) : 100;
   }
 }
@@ -0,0 +1,144 @@
+import { Component, OnDestroy, OnInit } from '@angular/core';
+import { FormsModule } from '@angular/forms';
+import { invoke } from '@tauri-apps/api/core';
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
+import { FileConflictModel, FileFolderModel, FileModel, SendRequestModel, TextMessageModel } from '../../shared/models/send-request.model';
+import { ConflictPolicy } from '../../shared/models/settings.model';
+import { UntrustedDeviceModel } from '../../shared/models/device.model';
+import { formatBytes } from '../../shared/utils';
+
+@Component({
+  selector: 'app-send-request',
+  standalone: true,
+  imports: [FormsModule],
+  templateUrl: './send-request.component.html',
+  styleUrl: './send-request.component.css'
+})
+export class SendRequestComponent implements OnInit, OnDestroy {
+  // several senders can ask at the same time, they are shown one after the other
+  requests: SendRequestModel[] = [];
+  selected: { [fileId: string]: boolean } = {};
+  // add the sender of the current request to the favorite devices when it is accepted
+  add_favorite = false;
+  conflicts: FileConflictModel[] = [];
+  // text messages are only shown, nothing has to be answered
+  messages: TextMessageModel[] = [];
+  // discovery reports a device again whenever a new one shows up, each is warned about once
+  untrusted_devices: UntrustedDeviceModel[] = [];
+  private warned = new Set<string>();
+  // files of the requests, conflicts are reported after a request was answered
+  files: FileModel[] = [];
+  formatBytes = formatBytes;
+  private unlisten: UnlistenFn[] = [];
+
+  get request(): SendRequestModel | null {
+    return this.requests[0] ?? null;
+  }
+
+  get conflict(): FileConflictModel | null {
+    return this.conflicts[0] ?? null;
+  }
+
+  get message(): TextMessageModel | null {
+    return this.messages[0] ?? null;
+  }
+
+  get untrusted_device(): UntrustedDeviceModel | null {
+    return this.untrusted_devices[0] ?? null;
+  }
+
+  async ngOnInit() {
+    this.unlisten.push(await listen<SendRequestModel>('send-request', (event) => {
+      this.requests.push(event.payload);
+      this.files.push(...event.payload.files);
+      for (const file of event.payload.files) {
+        this.selected[file.id] = true;
+      }
+    }));
+    this.unlisten.push(await listen<TextMessageModel>('text-message', (event) => {
+      this.messages.push(event.payload);
+    }));
+    this.unlisten.push(await listen<UntrustedDeviceModel>('untrusted-device', (event) => {
+      const key = `${event.payload.device.alias} ${event.payload.device.fingerprint}`;
+      if (!this.warned.has(key)) {
+        this.warned.add(key);
+        this.untrusted_devices.push(event.payload);
+      }
+    }));
+    this.unlisten.push(await listen<FileConflictModel>('file-conflict', (event) => {
+      this.conflicts.push(event.payload);
+    }));
+    // the receiver stopped waiting for these answers, the requests were declined or the files skipped
+    this.unlisten.push(await listen<string[]>('prompt-expired', (event) => {
+      this.requests = this.requests.filter((request) => !event.payload.includes(request.requestId));
+      this.conflicts = this.conflicts.filter((conflict) => !event.payload.includes(conflict.conflictId));
+    }));
+  }
+
+  ngOnDestroy(): void {
+    this.unlisten.forEach((unlisten) => unlisten());
+  }
+
+  // files of a folder are listed as the folder, they are accepted as a whole
+  loose_files(request: SendRequestModel): FileModel[] {
+    const in_folder = new Set(request.folders.flatMap((folder) => folder.fileIds));
+    return request.files.filter((file) => !in_folder.has(file.id));
+  }
+
+  folder_selected(folder: FileFolderModel): boolean {
+    return folder.fileIds.every((fileId) => this.selected[fileId]);
+  }
+
+  select_folder(folder: FileFolderModel, selected: boolean) {
+    for (const fileId of folder.fileIds) {
+      this.selected[fileId] = selected;
+    }
+  }
+
+  file_name(fileId: string): string {
+    return this.files.find((file) => file.id === fileId)?.fileName ?? fileId;
+  }
+
+  respond(accept: boolean) {
+    const request = this.requests.shift();
+    if (!request) {
+      return;
+    }
+    const selectedFileIds = accept
+      ? request.files.filter((file) => this.selected[file.id]).map((file) => file.id)
+      : null;
+    if (accept && this.add_favorite && request.sender.fingerprint) {
+      const sender = request.sender;
+      invoke('set_favorite_device', { fingerprint: sender.fingerprint, alias: sender.alias, favorite: true })
+        .catch((error) => console.error(error));
+    }
+    this.add_favorite = false;
+    invoke('respond_to_send_request', { requestId: request.requestId, selectedFileIds })
+      .catch((error) => console.error(error));
+  }
+
+  copy_message() {
+    const message = this.messages.shift();
+    if (message) {
+      navigator.clipboard.writeText(message.text)
+        .catch((error) => console.error(error));
+    }
+  }
+
+  close_message() {
+    this.messages.shift();
+  }
+
+  close_untrusted_device() {
+    this.untrusted_devices.shift();
+  }
+
+  resolve_conflict(conflictPolicy: ConflictPolicy) {
+    const conflict = this.conflicts.shift();
+    if (!conflict) {
+      return;
+    }
+    invoke('resolve_file_conflict', { conflictId: conflict.conflictId, conflictPolicy })
+      .catch((error) => console.error(error));
+  }
+}
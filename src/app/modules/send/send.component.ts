@@ -1,16 +1,20 @@
 import { AfterViewInit, Component } from '@angular/core';
+import { FormsModule } from '@angular/forms';
 import { invoke } from '@tauri-apps/api/core';
 import { DeviceModel } from '../../shared/models/device.model';
 
 @Component({
   selector: 'app-send',
   standalone: true,
-  imports: [],
+  imports: [FormsModule],
   templateUrl: './send.component.html',
   styleUrl: './send.component.css'
 })
 export class SendComponent implements AfterViewInit {
   nearby_devices: DeviceModel[] = []
+  text = '';
+  // only needed for receivers that require one
+  pin = '';
   constructor() {
 
   }
@@ -27,4 +31,10 @@ export class SendComponent implements AfterViewInit {
       })
       .catch((error) => console.error(error));
   }
+
+  send_text(device: DeviceModel) {
+    invoke('send_text', { fingerprint: device.fingerprint, text: this.text, pin: this.pin || null })
+      .then(() => this.text = '')
+      .catch((error) => console.error(error));
+  }
 }
@@ -1,7 +1,10 @@
-import { Component } from '@angular/core';
+import { Component, OnInit } from '@angular/core';
 import { InputTextModule } from 'primeng/inputtext';
 import { DropdownModule } from 'primeng/dropdown';
 import { FormsModule } from '@angular/forms';
+import { invoke } from '@tauri-apps/api/core';
+import { FavoriteDeviceModel, SettingsModel } from '../../shared/models/settings.model';
+import { TrustedDeviceModel } from '../../shared/models/device.model';
 @Component({
   selector: 'app-settings',
   standalone: true,
@@ -9,8 +12,100 @@ import { FormsModule } from '@angular/forms';
   templateUrl: './settings.component.html',
   styleUrl: './settings.component.css'
 })
-export class SettingsComponent {
+export class SettingsComponent implements OnInit {
   device_name = 'Good Tomato';
   multicast_address = '224.0.0.167';
   port = 53317;
+  settings: SettingsModel | null = null;
+  fingerprint = '';
+  trusted_devices: TrustedDeviceModel[] = [];
+  conflict_policies = [
+    { label: 'Keep both', value: 'rename' },
+    { label: 'Overwrite', value: 'overwrite' },
+    { label: 'Skip', value: 'skip' },
+    { label: 'Ask', value: 'ask' }
+  ];
+  receive_policies = [
+    { label: 'Ask about every request', value: 'ask' },
+    { label: 'Accept favorite devices', value: 'favorites' },
+    { label: 'Accept everyone', value: 'everyone' }
+  ];
+
+  // the limits are edited as text and stored as lists and bytes
+  get auto_accept_file_types(): string {
+    return this.settings?.autoAcceptFileTypes.join(', ') ?? '';
+  }
+
+  set auto_accept_file_types(value: string) {
+    if (this.settings) {
+      this.settings.autoAcceptFileTypes = split_list(value);
+    }
+  }
+
+  get auto_accept_extensions(): string {
+    return this.settings?.autoAcceptExtensions.join(', ') ?? '';
+  }
+
+  set auto_accept_extensions(value: string) {
+    if (this.settings) {
+      this.settings.autoAcceptExtensions = split_list(value).map((extension) => extension.replace(/^\./, ''));
+    }
+  }
+
+  get auto_accept_max_size_mb(): number | null {
+    const maxSize = this.settings?.autoAcceptMaxSize ?? null;
+    return maxSize === null ? null : maxSize / 1_000_000;
+  }
+
+  set auto_accept_max_size_mb(value: number | null) {
+    if (this.settings) {
+      this.settings.autoAcceptMaxSize = value ? Math.round(value * 1_000_000) : null;
+    }
+  }
+
+  ngOnInit(): void {
+    invoke('get_settings')
+      .then((settings) => this.settings = settings as SettingsModel)
+      .catch((error) => console.error(error));
+    invoke('get_fingerprint')
+      .then((fingerprint) => this.fingerprint = fingerprint as string)
+      .catch((error) => console.error(error));
+    invoke('get_trusted_devices')
+      .then((trusted_devices) => this.trusted_devices = trusted_devices as TrustedDeviceModel[])
+      .catch((error) => console.error(error));
+  }
+
+  // devices that marked this one as a favorite won't recognize it anymore
+  regenerate_identity() {
+    if (!confirm('Other devices will not recognize this device anymore. Regenerate the identity?')) {
+      return;
+    }
+    invoke('regenerate_identity')
+      .then((fingerprint) => this.fingerprint = fingerprint as string)
+      .catch((error) => console.error(error));
+  }
+
+  save_settings() {
+    invoke('update_settings', { settings: this.settings })
+      .catch((error) => console.error(error));
+  }
+
+  // it is pinned again with whatever fingerprint it has the next time it is seen
+  forget_trusted_device(device: TrustedDeviceModel) {
+    invoke('forget_trusted_device', { fingerprint: device.fingerprint })
+      .then((trusted_devices) => this.trusted_devices = trusted_devices as TrustedDeviceModel[])
+      .catch((error) => console.error(error));
+  }
+
+  remove_favorite(device: FavoriteDeviceModel) {
+    invoke('set_favorite_device', { fingerprint: device.fingerprint, alias: device.alias, favorite: false })
+      .then((settings) => this.settings = settings as SettingsModel)
+      .catch((error) => console.error(error));
+  }
+}
+
+function split_list(value: string): string[] {
+  return value.split(',')
+    .map((item) => item.trim().toLowerCase())
+    .filter((item) => item.length > 0);
 }
@@ -2,5 +2,19 @@ export type DeviceModel = {
     alias: string,
     deviceType: string,
     deviceModel: string,
+    fingerprint: string,
     ipEnding: string
-}
\ No newline at end of file
+}
+// a device whose fingerprint was pinned the first time it was seen
+export type TrustedDeviceModel = {
+    fingerprint: string,
+    alias: string,
+    ip: string,
+    firstSeen: string
+}
+
+export type UntrustedDeviceModel = {
+    device: DeviceModel,
+    pinned: TrustedDeviceModel,
+    blocked: boolean
+}
@@ -0,0 +1,19 @@
+export type ReceiveStatus = 'waiting' | 'receiving' | 'finished' | 'finishedWithErrors' | 'cancelled';
+
+export type HistoryFileModel = {
+    fileName: string,
+    path: string,
+    size: number,
+    status: ReceiveStatus,
+    error: string | null,
+    preview?: string
+}
+
+export type HistoryEntryModel = {
+    sessionId: string,
+    senderAlias: string,
+    receivedAt: string,
+    status: ReceiveStatus,
+    folder: string | null,
+    files: HistoryFileModel[]
+}
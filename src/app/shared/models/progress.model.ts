@@ -0,0 +1,28 @@
+import { ReceiveStatus } from './history.model';
+import { FileModel } from './send-request.model';
+
+export type FileProgressModel = {
+    sessionId: string,
+    fileId: string,
+    fileName: string,
+    bytesDone: number,
+    totalBytes: number,
+    speed: number,
+    eta: number | null,
+    status: ReceiveStatus,
+    error: string | null
+}
+
+export type SessionEventKind = 'started' | 'finished' | 'finishedWithErrors' | 'cancelled' | 'timedOut';
+
+export type SessionEventModel = {
+    sessionId: string,
+    kind: SessionEventKind,
+    files: FileModel[]
+}
+
+export type ReceivingSessionModel = {
+    sessionId: string,
+    state: SessionEventKind,
+    files: FileProgressModel[]
+}
@@ -0,0 +1,42 @@
+import { DeviceModel } from './device.model';
+
+export type FileModel = {
+    id: string,
+    size: number,
+    fileName: string,
+    fileType: string,
+    sha256?: string,
+    preview?: string
+}
+
+export type SendRequestModel = {
+    requestId: string,
+    sender: DeviceModel,
+    files: FileModel[],
+    folders: FileFolderModel[],
+    totalSize: number,
+    spaceShortage: SpaceShortageModel | null
+}
+
+export type FileFolderModel = {
+    name: string,
+    fileIds: string[],
+    size: number
+}
+
+export type SpaceShortageModel = {
+    directory: string,
+    needed: number,
+    free: number
+}
+
+export type TextMessageModel = {
+    sender: DeviceModel,
+    text: string
+}
+
+export type FileConflictModel = {
+    conflictId: string,
+    fileId: string,
+    path: string
+}
@@ -0,0 +1,27 @@
+export type ConflictPolicy = 'rename' | 'overwrite' | 'skip' | 'ask';
+
+export type ReceivePolicy = 'ask' | 'favorites' | 'everyone';
+
+export type FavoriteDeviceModel = {
+    fingerprint: string,
+    alias: string
+}
+
+export type SettingsModel = {
+    downloadDirectory: string,
+    sessionSubfolders: boolean,
+    routeByFileType: boolean,
+    conflictPolicy: ConflictPolicy,
+    promptTimeout: number,
+    idleTimeout: number,
+    stallTimeout: number,
+    maxSessions: number,
+    maxParallelUploads: number,
+    receivePolicy: ReceivePolicy,
+    favoriteDevices: FavoriteDeviceModel[],
+    autoAcceptMaxSize: number | null,
+    autoAcceptFileTypes: string[],
+    autoAcceptExtensions: string[],
+    receivePin: string | null,
+    blockUntrustedDevices: boolean
+}
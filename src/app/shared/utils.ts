@@ -0,0 +1,10 @@
+const UNITS = ['B', 'KiB', 'MiB', 'GiB', 'TiB'];
+
+export function formatBytes(bytes: number): string {
+    let unit = 0;
+    while (bytes >= 1024 && unit < UNITS.length - 1) {
+        bytes /= 1024;
+        unit++;
+    }
+    return `${unit === 0 ? bytes : bytes.toFixed(2)} ${UNITS[unit]}`;
+}